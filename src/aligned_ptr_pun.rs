@@ -1,31 +1,39 @@
-use core::prelude::*;
-
 pub struct AlignedPtrPun<T>(*mut T);
 
+impl<T> Clone for AlignedPtrPun<T> {
+  #[inline]
+  fn clone(&self) -> AlignedPtrPun<T> {
+    *self
+  }
+}
+
 impl<T> Copy for AlignedPtrPun<T> { }
 
 impl<T> AlignedPtrPun<T>
 {
+  #[inline]
+  pub const fn null() -> AlignedPtrPun<T> {
+    AlignedPtrPun(::core::ptr::null_mut())
+  }
+
   #[inline]
   pub fn new(ptr: *mut T, flag: bool) -> AlignedPtrPun<T> {
-    debug_assert_eq!(ptr as uint & 1, 0);
-    let bits = (ptr as uint) | (flag as uint);
-    AlignedPtrPun(bits as *mut T)
+    debug_assert_eq!(ptr.addr() & 1, 0);
+    AlignedPtrPun(ptr.map_addr(|bits| bits | flag as usize))
   }
 
   #[inline]
   pub fn eliminate(self) -> (*mut T, bool) {
-    let bits = self.0 as uint;
-    ((bits & !1) as *mut T, (bits & 1) == 1)
+    (self.0.map_addr(|bits| bits & !1), (self.0.addr() & 1) == 1)
   }
 
   #[inline]
   pub fn set_ptr(&mut self, ptr: *mut T) {
-    *self = AlignedPtrPun::new(ptr, self.0 as uint == 1);
+    *self = AlignedPtrPun::new(ptr, self.eliminate().1);
   }
 
   #[inline]
   pub fn set_flag(&mut self, flag: bool) {
-    self.0 = (self.0 as uint & (flag as uint & -2)) as *mut T;
+    self.0 = self.0.map_addr(|bits| (bits & !1) | flag as usize);
   }
 }
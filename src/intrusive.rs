@@ -1,17 +1,18 @@
+/// Implement this to tell a container where in `Self` its bookkeeping
+/// `Struct` lives, in effect giving it the field's offset.
 pub trait Intrusive<Struct>
 {
-  #[inline]
-  fn field(&mut self) -> &mut Struct;
+  fn field(&self) -> &Struct;
 }
 
-pub trait IntrusiveExt<U> {
-  #[inline]
-  fn field(&self) -> &mut U;
+pub(crate) trait IntrusiveExt<U> {
+  fn field(&self) -> &U;
 }
 
 impl<T, U> IntrusiveExt<U> for *mut T where T: Intrusive<U> {
   #[inline]
-  fn field(&self) -> &mut U {
-    unsafe { &mut **self }.field()
+  fn field(&self) -> &U {
+    // Containers only ever call this on pointers to linked, live elements.
+    unsafe { &**self }.field()
   }
 }
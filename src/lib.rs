@@ -1,9 +1,5 @@
 #![no_std]
-#![feature(phase)]
-#![feature(globs)]
 
-#[phase(plugin, link)]
-extern crate core;
 extern crate alloc;
 
 mod intrusive;
@@ -11,19 +7,8 @@ mod aligned_ptr_pun;
 
 pub mod red_black;
 
+pub use intrusive::Intrusive;
+
 #[test]
 fn it_works() {
 }
-
-
-// for deriving and macros
-#[doc(hidden)]
-#[cfg(not(test))]
-mod std {
-  pub use core::clone;
-  pub use core::cmp;
-  pub use core::kinds;
-  pub use core::option;
-  pub use core::fmt;
-  pub use core::hash;
-}
@@ -1,748 +1,2595 @@
-use core::prelude::*;
+use core::borrow::Borrow;
+use core::cell::Cell;
+use core::cmp::Ordering::{self, Equal, Greater, Less};
+use core::fmt;
+use core::marker::{PhantomData, PhantomPinned};
+use core::mem;
+use core::ptr::{self, NonNull};
 
-use core::mem::uninitialized;
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use crate::aligned_ptr_pun::AlignedPtrPun;
 
-use intrusive::{Intrusive, IntrusiveExt};
-use aligned_ptr_pun::AlignedPtrPun;
+pub use self::cursor::{Cursor, CursorMut, ExtractIf};
+pub use self::entry::{Entry, OccupiedEntry, VacantEntry};
+pub use self::iter::{Drain, Iter, IterMut, Range, RangeMut};
+
+/// `assert!`, but handing corruption back to the caller rather than
+/// panicking.
+macro_rules! ensure {
+  ($cond:expr) => {
+    if !$cond {
+      return Err(TreeError::CorruptLinks)
+    }
+  };
+}
+
+mod bulk;
+mod cursor;
+mod entry;
+#[macro_use]
+mod iter;
+mod join;
+mod set;
+
+pub mod interval;
+pub mod parent;
 
 
 /// The fields requied to be in a node to store it in a intrusive red-black
 /// tree.
 ///
 /// Add this to your type, T, and implement `Intrusive<Node<T>>` to, in effect,
-/// tell this library what the offset is.q
+/// tell this library what the offset is. A node may also keep a summary `S` of
+/// its subtree, such as `Size`, for trees that need one.
+///
+/// Instances of your type should be created with `Node::new()`; the tree
+/// overwrites the links when the element is inserted.
 ///
-/// Instances of your type should be created with `Node<T>` uninitailized.
-pub struct Node<T> {
-  left:      *mut T,
-  right_red: AlignedPtrPun<T>,
+/// A node is `!Unpin`, and so is any type holding one, so that the pinned
+/// references handed out by `iter_mut` can't be used to move an element out
+/// from under its links.
+pub struct Node<T, S = ()> {
+  left:      Cell<*mut T>,
+  right_red: Cell<AlignedPtrPun<T>>,
+  summary:   Cell<S>,
+  _pinned:   PhantomPinned,
 }
 
-// To make allow users to derive PartialOrd without causing problems
-impl<T> PartialEq for Node<T> {
-  fn eq(&self, _other: &Self) -> bool {
-    // Equality of things in intrusive containers does NOT depend on their
-    // position in container
-    true
-  }
-  fn ne(&self, _other: &Self) -> bool {
-    // Equality of things in intrusive containers does NOT depend on their
-    // position in container
-    false
+impl<T, S> Default for Node<T, S> where S: Augment<T> {
+  #[inline]
+  fn default() -> Node<T, S> {
+    Node::new()
   }
 }
 
-impl<T> PartialOrd for Node<T> {
-  fn partial_cmp(&self, _other: &Self) -> Option<Ordering> {
-    // Equality of things in intrusive containers does NOT depend on their
-    // position in container
-    Some(Equal)
+impl<T, S> Node<T, S> where S: Augment<T>
+{
+  /// An unlinked node.
+  #[inline]
+  pub const fn new() -> Node<T, S> {
+    Node {
+      left:      Cell::new(ptr::null_mut()),
+      right_red: Cell::new(AlignedPtrPun::null()),
+      summary:   Cell::new(S::EMPTY),
+      _pinned:   PhantomPinned,
+    }
+  }
+
+  /// The summary of the subtree under this node.
+  #[inline]
+  pub fn summary(&self) -> S {
+    self.summary.get()
   }
 }
 
-impl<T> Node<T> where T: Intrusive<Node<T>> + PartialOrd
+impl<T, S> Node<T, S>
 {
   #[inline]
-  pub fn new(tree: &mut Tree<T>) -> Node<T> {
-    Node {
-      left:      tree.nil_ref(),
-      right_red: AlignedPtrPun::new(tree.nil_ref(), true),
-    }
+  fn reset(&self, color: bool) {
+    self.left.set(ptr::null_mut());
+    self.right_red.set(AlignedPtrPun::new(ptr::null_mut(), color));
+  }
+
+  #[inline]
+  fn left(&self) -> *mut T {
+    self.left.get()
   }
 
   #[inline]
-  fn right(&mut self) -> *mut T {
-    self.right_red.eliminate().0
+  fn set_left(&self, ptr: *mut T) {
+    self.left.set(ptr)
   }
 
   #[inline]
-  fn set_right(&mut self, ptr: *mut T) {
-    self.right_red.set_ptr(ptr)
+  fn right(&self) -> *mut T {
+    self.right_red.get().eliminate().0
   }
 
   #[inline]
-  fn color(&mut self) -> bool {
-    self.right_red.eliminate().1
+  fn set_right(&self, ptr: *mut T) {
+    let mut right_red = self.right_red.get();
+    right_red.set_ptr(ptr);
+    self.right_red.set(right_red)
   }
 
+  /// `true` is red, `false` is black.
   #[inline]
-  fn set_color(&mut self, color: bool) {
-    self.right_red.set_flag(color)
+  fn color(&self) -> bool {
+    self.right_red.get().eliminate().1
+  }
+
+  #[inline]
+  fn set_color(&self, color: bool) {
+    let mut right_red = self.right_red.get();
+    right_red.set_flag(color);
+    self.right_red.set(right_red)
   }
 }
 
-trait NodeExt  {
-  fn rotate_left(&mut self) -> Self;
-  fn rotate_right(&mut self) -> Self;
+/// A summary a node keeps of its subtree, such as a count, sum or maximum,
+/// recomputed whenever the subtree changes: on the way back up from a link or
+/// unlink, and in each rotation.
+///
+/// `combine` must be associative, as in a monoid with `EMPTY` as identity: the
+/// summary of a run of elements must not depend on how the tree groups them.
+/// And as with keys, whatever `combine` reads of an element must not change
+/// while it is linked.
+pub trait Augment<T>: Copy {
+  /// The summary of an empty subtree.
+  const EMPTY: Self;
+
+  /// The summary of a subtree, given its root and its children's summaries.
+  fn combine(left: Self, node: &T, right: Self) -> Self;
+
+  /// The number of elements summarized, for summaries that keep count. Trees
+  /// iterate with exact size hints if it is known.
+  #[inline]
+  fn count(self) -> Option<usize> {
+    None
+  }
 }
 
-impl<T>  NodeExt for *mut T where T: Intrusive<Node<T>> + PartialOrd
-{
+/// No summary at all.
+impl<T> Augment<T> for () {
+  const EMPTY: () = ();
+
   #[inline]
-  fn rotate_left(&mut self) -> Self {
-    let old_right = self.field().right();
-    self.field().set_right(old_right.field().left);
-    old_right.field().left = *self;
-    old_right
+  fn combine(_: (), _: &T, _: ()) {}
+}
+
+/// The number of elements in a subtree, for `len`, `select` and `rank`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Size(pub usize);
+
+impl<T> Augment<T> for Size {
+  const EMPTY: Size = Size(0);
+
+  #[inline]
+  fn combine(left: Size, _: &T, right: Size) -> Size {
+    Size(left.0 + 1 + right.0)
   }
 
   #[inline]
-  fn rotate_right(&mut self) -> Self {
-    let old_left = self.field().left;
+  fn count(self) -> Option<usize> {
+    Some(self.0)
+  }
+}
 
-    self.field().left = old_left.field().right();
-    old_left.field().set_right(*self);
-    old_left
+/// Whether a tree keeps a count of its elements, for `len` and exact-size
+/// iteration: `()` keeps none, and `usize` does. The count takes one word on
+/// the tree and none on its nodes, but `split_off` must then count the
+/// elements it moves.
+pub trait Counter: Copy {
+  /// The count of an empty tree.
+  const ZERO: Self;
+
+  /// The number of elements, if counted.
+  fn get(self) -> Option<usize>;
+
+  /// Count `n` more elements.
+  fn add(&mut self, n: usize);
+
+  /// Count `n` fewer elements.
+  fn sub(&mut self, n: usize);
+}
+
+/// No count at all.
+impl Counter for () {
+  const ZERO: () = ();
+
+  #[inline]
+  fn get(self) -> Option<usize> {
+    None
   }
+
+  #[inline]
+  fn add(&mut self, _: usize) {}
+
+  #[inline]
+  fn sub(&mut self, _: usize) {}
 }
 
-/// Left-leaning 2-3 red-black trees.  Parent pointers are not used, and color
-/// bits are stored in the least significant bit of right-child pointers thus
-/// making node linkage as compact as is possible for red-black trees.
-///
-/// Ported from https://github.com/thestinger/allocator/blob/master/rb.h. In
-/// turn from jemalloc.
-pub struct Tree<T> {
-  root: *mut T,
-  nil:  T
+impl Counter for usize {
+  const ZERO: usize = 0;
+
+  #[inline]
+  fn get(self) -> Option<usize> {
+    Some(self)
+  }
+
+  #[inline]
+  fn add(&mut self, n: usize) {
+    *self += n
+  }
+
+  #[inline]
+  fn sub(&mut self, n: usize) {
+    *self -= n
+  }
+}
+
+/// The links of a node of either kind, for what `Tree` and `ParentTree` do
+/// alike: searching, stepping down, and keeping summaries.
+pub(super) trait Links<T> {
+  type Summary: Augment<T>;
+
+  fn left(&self) -> *mut T;
+  fn right(&self) -> *mut T;
+  /// `true` is red, `false` is black.
+  fn color(&self) -> bool;
+  fn summary(&self) -> Self::Summary;
+  fn set_summary(&self, summary: Self::Summary);
 }
 
-impl<T> Tree<T> where T: Intrusive<Node<T>> + PartialOrd
+impl<T, S> Links<T> for Node<T, S> where S: Augment<T>
 {
+  type Summary = S;
+
   #[inline]
-  pub fn place() -> Tree<T> {
-    unsafe { uninitialized() }
+  fn left(&self) -> *mut T {
+    Node::left(self)
   }
 
   #[inline]
-  pub fn init(&mut self) {
-    self.root = self.nil_ref();
-    *self.nil.field() = Node {
-      left:      self.nil_ref(),
-      right_red: AlignedPtrPun::new(self.nil_ref(), false),
-    };
+  fn right(&self) -> *mut T {
+    Node::right(self)
   }
 
-  fn nil_ref(&mut self) -> *mut T {
-    &mut self.nil as *mut T
+  #[inline]
+  fn color(&self) -> bool {
+    Node::color(self)
   }
 
-  // Utils, actual functions subst null ptr for sentinal ptr
+  #[inline]
+  fn summary(&self) -> S {
+    self.summary.get()
+  }
 
   #[inline]
-  fn first_(&mut self, subtree: *mut T) -> *mut T {
-    let mut node = subtree;
+  fn set_summary(&self, summary: S) {
+    self.summary.set(summary)
+  }
+}
 
-    if node != self.nil_ref() {
-      while node.field().left != self.nil_ref() {
-        node = node.field().left;
-      }
-    }
-    node
+/// The summary of the subtree at `node`, which may be a null leaf.
+#[inline]
+fn summary<T, L>(node: *mut T) -> L::Summary where T: Intrusive<L>, L: Links<T> {
+  if node.is_null() {
+    L::Summary::EMPTY
+  } else {
+    node.field().summary()
+  }
+}
+
+/// Recompute the summary of `node` from its children's.
+#[inline]
+fn update<T, L>(node: *mut T) where T: Intrusive<L>, L: Links<T> {
+  // Nothing to keep, so nothing to do
+  if mem::size_of::<L::Summary>() == 0 {
+    return
+  }
+  let links = node.field();
+  links.set_summary(L::Summary::combine(summary(links.left()), unsafe { &*node }, summary(links.right())));
+}
+
+/// Recompute the summaries of `path[depth - 1]` up to the root, whose
+/// subtrees have changed below them.
+#[inline]
+fn update_path<T, S>(path: &[PathElem<T>], depth: usize) where T: Intrusive<Node<T, S>>, S: Augment<T> {
+  if mem::size_of::<S>() == 0 {
+    return
+  }
+  for elem in path[..depth].iter().rev() {
+    update(elem.node);
+  }
+}
+
+trait NodeExt<S> {
+  fn rotate_left(self) -> Self;
+  fn rotate_right(self) -> Self;
+}
+
+impl<T, S> NodeExt<S> for *mut T where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  #[inline]
+  fn rotate_left(self) -> Self {
+    let old_right = self.field().right();
+    self.field().set_right(old_right.field().left());
+    old_right.field().set_left(self);
+    update(self);
+    update(old_right);
+    old_right
   }
 
   #[inline]
-  fn last_(&mut self, subtree: *mut T) -> *mut T {
-    let mut node = subtree;
+  fn rotate_right(self) -> Self {
+    let old_left = self.field().left();
+    self.field().set_left(old_left.field().right());
+    old_left.field().set_right(self);
+    update(self);
+    update(old_left);
+    old_left
+  }
+}
+
+/// Null leaves count as black.
+#[inline]
+fn is_red<T, L>(node: *mut T) -> bool where T: Intrusive<L>, L: Links<T> {
+  !node.is_null() && node.field().color()
+}
+
+/// The first element of the subtree at `node`, or null if it is empty.
+#[inline]
+fn leftmost<T, L>(mut node: *mut T) -> *mut T where T: Intrusive<L>, L: Links<T> {
+  if !node.is_null() {
+    while !node.field().left().is_null() {
+      node = node.field().left();
+    }
+  }
+  node
+}
+
+/// The last element of the subtree at `node`, or null if it is empty.
+#[inline]
+fn rightmost<T, L>(mut node: *mut T) -> *mut T where T: Intrusive<L>, L: Links<T> {
+  if !node.is_null() {
+    while !node.field().right().is_null() {
+      node = node.field().right();
+    }
+  }
+  node
+}
+
+// Searches by closure. `f` reports how an element compares to the target,
+// as with `slice::binary_search_by`.
 
-    if node != self.nil_ref() {
-      while node.field().right() != self.nil_ref() {
+/// Descend from `root` as `f` directs, reading its `Equal` as `tie`, down to
+/// the leaf between the elements it puts before the target and those it puts
+/// after. Returns the last before and the first after, either of which may be
+/// null. If `tie` is `Equal`, stops at any element `f` reports equal instead,
+/// which is returned as both.
+#[inline]
+fn descend<T, L, F>(root: *mut T, mut f: F, tie: Ordering) -> (*mut T, *mut T)
+  where T: Intrusive<L>, L: Links<T>, F: FnMut(&T) -> Ordering
+{
+  let mut before = ptr::null_mut();
+  let mut after = ptr::null_mut();
+  let mut node = root;
+  while !node.is_null() {
+    let ord = match f(unsafe { &*node }) {
+      Equal => tie,
+      ord   => ord,
+    };
+    match ord {
+      Less    => {
+        before = node;
         node = node.field().right();
-      }
+      },
+      Greater => {
+        after = node;
+        node = node.field().left();
+      },
+      Equal   => return (node, node),
     }
-    node
   }
+  (before, after)
+}
+
+/// An element `f` reports `Equal`, or null.
+#[inline]
+fn search_<T, L, F>(root: *mut T, f: F) -> *mut T where T: Intrusive<L>, L: Links<T>, F: FnMut(&T) -> Ordering {
+  match descend(root, f, Equal) {
+    (before, after) if before == after => before,
+    _                                  => ptr::null_mut(),
+  }
+}
+
+/// The first element `f` does not report `Less`, or null.
+#[inline]
+fn nsearch_<T, L, F>(root: *mut T, f: F) -> *mut T where T: Intrusive<L>, L: Links<T>, F: FnMut(&T) -> Ordering {
+  descend(root, f, Greater).1
+}
+
+/// The last element `f` does not report `Greater`, or null.
+#[inline]
+fn psearch_<T, L, F>(root: *mut T, f: F) -> *mut T where T: Intrusive<L>, L: Links<T>, F: FnMut(&T) -> Ordering {
+  descend(root, f, Less).0
+}
+
+/// The first element `f` reports `Greater`, or null.
+#[inline]
+fn usearch_<T, L, F>(root: *mut T, f: F) -> *mut T where T: Intrusive<L>, L: Links<T>, F: FnMut(&T) -> Ordering {
+  descend(root, f, Less).1
+}
 
-  fn sanitize(&mut self, ptr: *mut T) -> *mut T {
-    if ptr == self.nil_ref() {
-      0 as *mut T
+/// The first element whose own summary `f` holds for, descending on its
+/// subtrees' summaries as `Tree::find_first_where` describes.
+#[inline]
+fn find_first_where_<T, L, F>(root: *mut T, mut f: F) -> *mut T
+  where T: Intrusive<L>, L: Links<T>, F: FnMut(L::Summary) -> bool
+{
+  search_(root, |elem: &T| {
+    let left = elem.field().left();
+    if !left.is_null() && f(summary(left)) {
+      Greater
+    } else if f(L::Summary::combine(L::Summary::EMPTY, elem, L::Summary::EMPTY)) {
+      Equal
     } else {
-      ptr
+      Less
     }
+  })
+}
+
+/// The element with `k` elements before it in the subtree at `node`, or null.
+#[inline]
+fn select_<T, L>(mut node: *mut T, mut k: usize) -> *mut T where T: Intrusive<L>, L: Links<T, Summary = Size> {
+  while !node.is_null() {
+    let left = node.field().left();
+    let before = summary(left).0;
+    node = match k.cmp(&before) {
+      Less    => left,
+      Equal   => return node,
+      Greater => {
+        k -= before + 1;
+        node.field().right()
+      },
+    };
   }
+  ptr::null_mut()
+}
 
-  #[inline]
-  pub fn first(&mut self) -> *mut T {
-    let ptr = self.first_(self.root);
-    self.sanitize(ptr)
+/// The number of elements in the subtree at `node` that `before` holds for,
+/// which must be a prefix of them.
+#[inline]
+fn rank_<T, L, F>(mut node: *mut T, mut before: F) -> usize
+  where T: Intrusive<L>, L: Links<T, Summary = Size>, F: FnMut(&T) -> bool
+{
+  let mut n = 0;
+  while !node.is_null() {
+    let left = node.field().left();
+    node = match before(unsafe { &*node }) {
+      true  => {
+        n += summary(left).0 + 1;
+        node.field().right()
+      },
+      false => left,
+    };
+  }
+  n
+}
+
+/// Why a `try_` operation on a tree failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeError {
+  /// The node is not linked into the tree.
+  NotLinked,
+  /// The node is already linked into the tree.
+  DuplicateKey,
+  /// The node's key does not compare equal to itself, so it has no place in
+  /// the order.
+  IncomparableKey,
+  /// The tree's links do not form a valid red-black tree.
+  CorruptLinks,
+  /// The node's key does not belong at the position it was to be put in.
+  OutOfOrder,
+}
+
+impl fmt::Display for TreeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match *self {
+      TreeError::NotLinked       => "node is not linked into the tree",
+      TreeError::DuplicateKey    => "node is already linked into the tree",
+      TreeError::IncomparableKey => "node's key is not equal to itself",
+      TreeError::CorruptLinks    => "tree links are corrupt",
+      TreeError::OutOfOrder      => "node's key is out of order at that position",
+    })
   }
+}
+
+/// The result of following a tree's links, for operations that panic rather
+/// than report corruption.
+#[inline]
+fn intact<R>(result: Result<R, TreeError>) -> R {
+  result.unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Where `wind` stopped: at the leaf where the node belongs, or at an element
+/// with an equal key.
+enum Slot {
+  Vacant(usize),
+  Occupied(usize),
+}
+
+/// Enough room for the deepest possible path through a tree filling the
+/// address space.
+const PATH_LEN: usize = (usize::BITS as usize) << 1;
 
+/// How a tree orders its elements: by a key picked out of each.
+///
+/// Implement this on a marker type per ordering, so that the same element type
+/// can be ordered by different fields in different trees.
+pub trait KeyAdapter<T> {
+  type Key: ?Sized;
+
+  fn key(node: &T) -> &Self::Key;
+}
+
+/// A comparator for keys, stored in the tree so that the order may depend on
+/// runtime state.
+pub trait Compare<K: ?Sized> {
+  fn compare(&self, a: &K, b: &K) -> Ordering;
+}
+
+/// The keys' own `Ord`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Natural;
+
+impl<K> Compare<K> for Natural where K: Ord + ?Sized {
   #[inline]
-  pub fn last(&mut self) -> *mut T {
-    let ptr = self.last_(self.root);
-    self.sanitize(ptr)
+  fn compare(&self, a: &K, b: &K) -> Ordering {
+    a.cmp(b)
   }
+}
 
+impl<K, F> Compare<K> for F where K: ?Sized, F: Fn(&K, &K) -> Ordering {
   #[inline]
-  pub fn next(&mut self, node: *mut T) -> *mut T {
-    debug_assert!(node != 0 as *mut T);
-    let mut ret;
-    if node.field().right() != self.nil_ref() {
-      ret = self.first_(node.field().right());
-    } else {
-      let mut tnode = self.root;
-      ret = self.nil_ref();
-      assert!(tnode != self.nil_ref());
-      loop {
-        tnode = match unsafe { (*node).partial_cmp(&*tnode) } {
-          None | Some(Less) => {
-            ret = tnode;
-            tnode.field().left
-          },
-          Some(Greater)     => tnode.field().right(),
-          Some(Equal)       => break,
-        };
-        assert!(tnode != self.nil_ref());
-      }
-    }
-    self.sanitize(ret)
+  fn compare(&self, a: &K, b: &K) -> Ordering {
+    self(a, b)
   }
+}
+
+/// Left-leaning 2-3 red-black trees.  Parent pointers are not used, and color
+/// bits are stored in the least significant bit of right-child pointers thus
+/// making node linkage as compact as is possible for red-black trees.
+///
+/// Ported from https://github.com/thestinger/allocator/blob/master/rb.h. In
+/// turn from jemalloc.
+///
+/// Leaves are null pointers, so the tree holds nothing but its root, and a
+/// count of its elements if `L` is `usize`, and may be moved freely, even
+/// while elements are linked into it. Its nodes may count their subtrees'
+/// elements too, if `S` is `Size`.
+///
+/// Elements are ordered by the key `A` picks out of them, as compared by `C`.
+/// Several elements may have equal keys, in which case they are kept in the
+/// order they were inserted.
+pub struct Tree<T, A, C = Natural, S = (), L = ()> {
+  root:     *mut T,
+  len:      L,
+  cmp:      C,
+  _adapter: PhantomData<(A, S)>,
+}
 
+impl<T, A, S, L> Tree<T, A, Natural, S, L> where L: Counter
+{
+  /// An empty tree, ordered by its keys' own `Ord`.
   #[inline]
-  pub fn prev(&mut self, node: *mut T) -> *mut T {
-    let mut ret;
-    if node.field().left != self.nil_ref() {
-      ret = self.last_(node.field().left);
-    } else {
-      let mut tnode = self.root;
-      ret = self.nil_ref();
-      assert!(tnode != self.nil_ref());
-      loop {
-        tnode = match unsafe { (*node).partial_cmp(&*tnode) } {
-          None | Some(Less) => tnode.field().left,
-          Some(Greater)     => {
-            ret   = tnode;
-            tnode.field().right()
-          },
-          Some(Equal)       => break,
-        };
-        assert!(tnode != self.nil_ref());
-      }
-    }
-    self.sanitize(ret)
+  pub const fn new() -> Tree<T, A, Natural, S, L> {
+    Tree::with_comparator(Natural)
   }
+}
 
+impl<T, A, C, S, L> Tree<T, A, C, S, L> where L: Counter
+{
+  /// An empty tree ordered by `cmp`.
   #[inline]
-  pub fn search(&mut self, key: *mut T) -> *mut T {
-    let mut ret = self.root;
-    while ret != self.nil_ref() {
-      ret = match unsafe { (*key).partial_cmp(&*ret) } {
-        None | Some(Less) => ret.field().left,
-        Some(Greater)     => ret.field().right(),
-        Some(Equal)       => break,
-      }
+  pub const fn with_comparator(cmp: C) -> Tree<T, A, C, S, L> {
+    Tree {
+      root:     ptr::null_mut(),
+      len:      L::ZERO,
+      cmp,
+      _adapter: PhantomData,
     }
-    self.sanitize(ret)
   }
 
   #[inline]
-  pub fn nsearch(&mut self, key: *mut T) -> *mut T {
-    let mut ret = self.nil_ref();
-    let mut tnode = self.root;
-    while tnode != self.nil_ref() {
-      tnode = match unsafe { (*key).partial_cmp(&*ret) } {
-        None | Some(Less) => {
-          ret = tnode;
-          ret.field().left
-        },
-        Some(Greater)     => ret.field().right(),
-        Some(Equal)       => {
-          ret = tnode;
-          break
-        },
-      }
-    }
-    self.sanitize(ret)
+  pub fn comparator(&self) -> &C {
+    &self.cmp
   }
 
   #[inline]
-  pub fn psearch(&mut self, key: *mut T) -> *mut T {
-    let mut ret = self.nil_ref();
-    let mut tnode = self.root;
-    while tnode != self.nil_ref() {
-      tnode = match unsafe { (*key).partial_cmp(&*ret) } {
-        None | Some(Less) => ret.field().left,
-        Some(Greater)     => {
-          ret = tnode;
-          ret.field().right()
-        },
-        Some(Equal)       => {
-          ret = tnode;
-          break
-        },
-      }
-    }
-    self.sanitize(ret)
+  pub fn is_empty(&self) -> bool {
+    self.root.is_null()
   }
+}
 
+impl<T, A, C, S, L> Default for Tree<T, A, C, S, L> where C: Default, L: Counter
+{
   #[inline]
-  pub fn insert(&mut self, node: *mut T) {
-    let mut path: [PathElem<T>, ..::core::uint::BITS << 1] = unsafe { uninitialized() };
-    *node.field() = Node::new(self);
+  fn default() -> Tree<T, A, C, S, L> {
+    Tree::with_comparator(C::default())
+  }
+}
 
-    // Wind
-    {
-      path[0].node = self.root;
-      let mut iter = path.iter_mut();
-      let mut cur  = iter.next().unwrap();
-      let mut next = iter.next().unwrap();
-      loop {
-        if cur.node == self.nil_ref() { break };
+impl<T, A, C, S> Tree<T, A, C, S, usize>
+{
+  /// The number of elements, in O(1) time.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.len
+  }
+}
 
-        cur.cmp = unsafe { (*node).partial_cmp(&*cur.node) };
-        next.node = match cur.cmp {
-          Some(Equal)       => unreachable!(),
-          None | Some(Less) => cur.node.field().left,
-          Some(Greater)     => cur.node.field().right(),
-        };
+impl<T, A, C, S, L> Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  // Utils
 
-        cur = next;
-        next = iter.next().unwrap();
-      }
-      cur.node = node;
-    }
-
-    // Unwind
-    {
-      let mut iter = path.iter_mut().rev();
-      let mut prev = iter.next().unwrap();
-      while let Some(cur) = iter.next() {
-        let mut cnode = cur.node;
-        cnode = match cur.cmp {
-          None | Some(Less) => {
-            let left = prev.node;
-            cnode.field().left = cnode;
-            if left.field().color() {
-              let left_left = left.field().left;
-              if left_left.field().color() {
-                // Fix up 4-node
-                left_left.field().set_color(false);
-                cnode.rotate_right()
-              } else {
-                cnode // keep current
-              }
-            } else {
-              return
-            }
-          },
-          #[cfg(not(ndebug))]
-          Some(Equal) => unreachable!(),
-          _ => {
-            let right = prev.node;
-            node.field().set_right(right);
-            if right.field().color() {
-              let left = right.field().left;
-              if left.field().color() {
-                // Split 3-node
-                left.field().set_color(false);
-                right.field().set_color(false);
-                cnode.field().set_color(true);
-                cnode // keep current
-              } else {
-                let tred = cnode.field().color();
-                let tnode = cnode.rotate_left();
-                tnode.field().set_color(tred);
-                cnode.field().set_color(true);
-                tnode
-              }
-            } else {
-              return
-            }
-          },
-        };
-        cur.node = cnode;
-        prev = cur;
-      }
-    }
+  #[inline]
+  fn order(&self, a: &T, b: &T) -> Ordering {
+    self.cmp.compare(A::key(a), A::key(b))
+  }
 
-    // Set root, and paint it black
-    self.root = path[0].node;
-    self.root.field().set_color(false);
+  #[inline]
+  pub fn first(&self) -> Option<NonNull<T>> {
+    NonNull::new(leftmost(self.root))
   }
 
-  pub fn remove(&mut self, node: *mut T) {
-    let mut path: [PathElem<T>, ..::core::uint::BITS << 1] = unsafe { uninitialized() };
+  #[inline]
+  pub fn last(&self) -> Option<NonNull<T>> {
+    NonNull::new(rightmost(self.root))
+  }
 
-    path[0].node = self.root;
-    {
-      let nodep;
-      let first_elem = &mut path[0] as *mut PathElem<T>;
-      let mut iter_1 = path.iter_mut();
-      {
-        let mut cur  = iter_1.next().unwrap();
-        let mut next = iter_1.next().unwrap();
-        loop {
-          assert!(cur.node != self.nil_ref()); // if node is in tree will never hit this
-
-          cur.cmp = unsafe { (*node).partial_cmp(&*cur.node) };
-          match cur.cmp {
-            None | Some(Less) => next.node = cur.node.field().left,
-            Some(Greater)     => next.node = cur.node.field().right(),
-            Some(Equal)       => {
-              next.node = cur.node.field().right();
-              break
-            }
-          }
-        }
-        cur.cmp = Some(Greater);
-        nodep = cur;
+  /// The element following `node`.
+  ///
+  /// # Safety
+  ///
+  /// `node` must be linked into this tree.
+  #[inline]
+  pub unsafe fn next(&self, node: NonNull<T>) -> Option<NonNull<T>> {
+    self.try_next(node).unwrap_or_else(|err| panic!("{}", err))
+  }
 
-        loop {
-          cur = next;
-          next = iter_1.next().unwrap();
+  /// The element preceding `node`.
+  ///
+  /// # Safety
+  ///
+  /// `node` must be linked into this tree.
+  #[inline]
+  pub unsafe fn prev(&self, node: NonNull<T>) -> Option<NonNull<T>> {
+    self.try_prev(node).unwrap_or_else(|err| panic!("{}", err))
+  }
 
-          if cur.node == self.nil_ref() { break };
+  /// The element following `node`, or `NotLinked` if `node` can't be found.
+  ///
+  /// # Safety
+  ///
+  /// `node` must point to a live element, though it need not be linked into
+  /// this tree.
+  pub unsafe fn try_next(&self, node: NonNull<T>) -> Result<Option<NonNull<T>>, TreeError> {
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    let i = self.path_to(node.as_ptr(), &mut path)?;
+    Ok(path_next(&mut path, i)?.map(|i| NonNull::new_unchecked(path[i].node)))
+  }
 
-          cur.cmp = Some(Less);
-          next.node = cur.node.field().left;
-        }
-        assert_eq!(nodep.node, node);
-      }
+  /// The element preceding `node`, or `NotLinked` if `node` can't be found.
+  ///
+  /// # Safety
+  ///
+  /// As for `try_next`.
+  pub unsafe fn try_prev(&self, node: NonNull<T>) -> Result<Option<NonNull<T>>, TreeError> {
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    let i = self.path_to(node.as_ptr(), &mut path)?;
+    Ok(path_prev(&mut path, i)?.map(|i| NonNull::new_unchecked(path[i].node)))
+  }
 
-      let mut iter_2 = iter_1.rev();
-      // thrice: pathp[1] -> pathp -> pathp-- -> path[-1]
-      iter_2.next();
-      {
-        let mut cur = iter_2.next().unwrap();
-        let next = iter_2.next().unwrap();
-
-        if cur.node != node {
-          // Swap node with its successor.
-          let tred = cur.node.field().color();
-          cur.node.field().set_color(node.field().color());
-          cur.node.field().left = node.field().left;
-          // If node's successor is its right child, the following code will do
-          // the wrong thing for the right child pointer.  However, it doesn't
-          // matter, because the pointer will be properly set when the successor
-          // is pruned.
-          cur.node.field().set_right(node.field().right());
-          node.field().set_color(tred);
-          // The pruned leaf node's chil pointers are never accessed again, so
-          // don't bother setting them to nil
-          nodep.node = cur.node;
-          cur.node = node;
-          if nodep as *mut PathElem<T> == first_elem {
-            self.root = nodep.node;
-          } else {
-            match next.cmp {
-              None | Some(Less) => next.node.field().left = nodep.node,
-              _                 => next.node.field().set_right(nodep.node),
-            }
-          }
-        } else {
-          let left = node.field().left;
-          if left != self.nil_ref() {
-            // node has no successor, but it has a left child.
-            // Splice node out, without losing the left child.
-            assert!(node.field().color() == false);
-            assert!(left.field().color() == true);
-            if cur as *mut PathElem<T> == cur as *mut PathElem<T>  {
-              self.root = nodep.node;
-            } else {
-              match next.cmp {
-                None | Some(Less) => next.node.field().left = left,
-                _                 => next.node.field().set_right(left),
-              }
-            }
-          } else if cur as *mut PathElem<T> == first_elem {
-            // The tree only contained one node
-            self.root = self.nil_ref();
-            return
-          }
-        }
-        if cur.node.field().color() == true {
-          // Prune red node, which reqires no fixup
-          assert!(next.cmp == Some(Less) || next.cmp == None);
-          next.node.field().left = self.nil_ref();
-          return
-        }
-        // The node to be pruned is black, so unwind until balance is restored.
-        // pathp -> pathp--
-        let mut prev = cur;
-        cur = next;
-        while let Some(next) = iter_2.next() {
-          match cur.cmp {
-            Some(Equal) => unreachable!(),
-            None | Some(Less) => {
-              cur.node.field().left = prev.node;
-              assert!(next.node.field().color() == true);
-              if cur.node.field().color() == true {
-                let mut right = cur.node.field().right();
-                let right_left = right.field().left;
-                let tnode = if right_left.field().color() == true {
-                  // In the following diagrams, ||, //, and
-                  // indicate the path to the removed node.
-                  //
-                  //      ||
-                  //    pathp(r)
-                  //  //        \
-                  // (b)        (b)
-                  //           /
-                  //          (r)
-                  //
-                  cur.node.field().set_color(false);
-                  cur.node.field().set_right(right.rotate_right());
-                  cur.node.rotate_left()
-                } else {
-                  //      ||
-                  //    pathp(r)
-                  //  //        \
-                  // (b)        (b)
-                  //           /
-                  //          (b)
-                  //
-                  cur.node.rotate_left()
-                };
-                // Balance restored, but rotation modified subtree
-                // root.
-                assert!(cur as *mut PathElem<T> > first_elem);
-                match next.cmp {
-                  None | Some(Less) => next.node.field().left = tnode,
-                  _ => next.node.field().set_right(tnode),
-                }
-                return
-              } else {
-                let mut right = cur.node.field().right();
-                let right_left = right.field().left;
-                if right_left.field().color() == true {
-                  //      ||
-                  //    pathp(b)
-                  //  //        \
-                  // (b)        (b)
-                  //           /
-                  //          (r)
-                  right_left.field().set_color(false);
-                  cur.node.field().set_right(right.rotate_right());
-                  let tnode = cur.node.rotate_left();
-                  // Balance restored, but rotation modified
-                  // subtree root, which may actually be the tree
-                  // root.
-                  if cur as *mut PathElem<T> == first_elem {
-                    self.root = tnode;
-                  } else {
-                    match next.cmp {
-                      None | Some(Less) => next.node.field().left = tnode,
-                      _                 => next.node.field().set_right(tnode),
-                    }
-                  }
-                  return
-                } else {
-                  //      ||
-                  //    pathp(b)
-                  //  //        \
-                  // (b)        (b)
-                  //           /
-                  //          (b)
-                  cur.node.field().set_color(true);
-                  cur.node = cur.node.rotate_left();
-                }
-              }
-            },
-            Some(Greater)     => {
-              cur.node.field().set_right(prev.node);
-              let left = cur.node.field().left;
-              if left.field().color() == true {
-                let tnode;
-                let left_right = left.field().right();
-                let left_right_left = left_right.field().left;
-                if left_right_left.field().color() == true {
-                  //      ||
-                  //    pathp(b)
-                  //   /        \\
-                  // (r)        (b)
-                  //   \
-                  //   (b)
-                  //   /
-                  // (r)
-                  left_right_left.field().set_color(false);
-                  let mut unode = cur.node.rotate_right();
-                  unode.field().set_right(cur.node.rotate_right());
-                  tnode = unode.rotate_left();
-                } else {
-                  //      ||
-                  //    pathp(b)
-                  //   /        \\
-                  // (r)        (b)
-                  //   \
-                  //   (b)
-                  //   /
-                  // (b)
-                  assert!(left_right != self.nil_ref());
-                  left_right.field().set_color(true);
-                  tnode = cur.node.rotate_right();
-                  tnode.field().set_color(false);
-                }
-                // Balance restored, but rotation modified subtree
-                // root, which may actually be the tree root.
-                if cur as *mut PathElem<T> == first_elem {
-                  // Set root.
-                  self.root = tnode;
-                } else {
-                  match next.cmp {
-                    None | Some(Less) => next.node.field().left = tnode,
-                    _                 => next.node.field().set_right(tnode),
-                  }
-                }
-                return;
-              } else if cur.node.field().color() == true {
-                let left_left = left.field().left;
-                if left_left.field().color() == true {
-                  //        ||
-                  //      pathp(r)
-                  //     /        \\
-                  //   (b)        (b)
-                  //   /
-                  // (r)
-                  cur.node.field().set_color(false);
-                  left.field().set_color(true);
-                  left_left.field().set_color(false);
-                  let tnode = cur.node.rotate_right();
-                  // Balance restored, but rotation modified
-                  // subtree root.
-                  assert!(cur as *mut PathElem<T> > first_elem);
-                  match next.cmp {
-                    None | Some(Less) => next.node.field().left = tnode,
-                    _                 => next.node.field().set_right(tnode),
-                  }
-                  return;
-                } else {
-                  //        ||
-                  //      pathp(r)
-                  //     /        \\
-                  //   (b)        (b)
-                  //   /
-                  // (b)
-                  left.field().set_color(true);
-                  cur.node.field().set_color(false);
-                  // Balance restored.
-                  return;
-                }
-              } else {
-                let left_left = left.field().left;
-                if left_left.field().color() == true {
-                  //               ||
-                  //             pathp(b)
-                  //            /        \\
-                  //          (b)        (b)
-                  //          /
-                  //        (r)
-                  left_left.field().set_color(false);
-                  let tnode = cur.node.rotate_right();
-                  // Balance restored, but rotation modified
-                  // subtree root, which may actually be the tree
-                  // root.
-                  if cur as *mut PathElem<T> == first_elem {
-                    // Set root.
-                    self.root = tnode;
-                  } else {
-                    match next.cmp {
-                      None | Some(Less) => next.node.field().left = tnode,
-                      _                 => next.node.field().set_right(tnode),
-                    }
-                  }
-                  return;
-                } else {
-                  //               ||
-                  //             pathp(b)
-                  //            /        \\
-                  //          (b)        (b)
-                  //          /
-                  //        (b)
-                  left.field().set_color(true);
-                }
-              }
-            }
-          }
-          prev = cur;
-          cur = next;
-        }
-      }
+  /// Record the path from the root to the first element `f` does not report
+  /// `Less`, returning its depth.
+  fn nsearch_path<F>(&self, mut f: F, path: &mut [PathElem<T>]) -> Result<Option<usize>, TreeError>
+    where F: FnMut(&T) -> Ordering
+  {
+    let mut ret = None;
+    let mut i = 0;
+    path[0].node = self.root;
+    while !path[i].node.is_null() {
+      let cur = path[i].node;
+      path[i].cmp = match f(unsafe { &*cur }) {
+        Less            => Greater,
+        Greater | Equal => {
+          ret = Some(i);
+          Less
+        },
+      };
+      ensure!(i + 1 < path.len());
+      path[i + 1].node = match path[i].cmp {
+        Less => cur.field().left(),
+        _    => cur.field().right(),
+      };
+      i += 1;
     }
-    // Set root
-    self.root = path[0].node;
-    assert_eq!(!self.root.field().color(), true);
+    Ok(ret)
   }
 
-
-  fn iter_recur<F, A>(&mut self, node: *mut T, cb: &mut F) -> Option<A>
-    where F: FnMut(&mut Self, *mut T) -> Option<A>
+  /// Record the path from the root to the last element `f` does not report
+  /// `Greater`, returning its depth.
+  fn psearch_path<F>(&self, mut f: F, path: &mut [PathElem<T>]) -> Result<Option<usize>, TreeError>
+    where F: FnMut(&T) -> Ordering
   {
-    if node == self.nil_ref() {
-      None
-    } else {
-      self.iter_recur(node.field().left, cb)
-        .or_else(|:| (*cb)(self, node))
-        .or_else(|:| self.iter_recur(node.field().right(), cb))
+    let mut ret = None;
+    let mut i = 0;
+    path[0].node = self.root;
+    while !path[i].node.is_null() {
+      let cur = path[i].node;
+      path[i].cmp = match f(unsafe { &*cur }) {
+        Greater      => Less,
+        Less | Equal => {
+          ret = Some(i);
+          Greater
+        },
+      };
+      ensure!(i + 1 < path.len());
+      path[i + 1].node = match path[i].cmp {
+        Less => cur.field().left(),
+        _    => cur.field().right(),
+      };
+      i += 1;
     }
+    Ok(ret)
   }
 
-  fn iter_start<F, A>(&mut self, start: &mut T, node: *mut T, cb: &mut F) -> Option<A>
-    where F: FnMut(&mut Self, *mut T) -> Option<A>
-  {
-    match (*start).partial_cmp(unsafe { &*node }) {
-      None | Some(Less) => {
-      self.iter_start(start, node.field().left, cb)
-          .or_else(|:| (*cb)(self, node))
-          .or_else(|:| self.iter_recur(node.field().right(), cb))
-      },
-      Some(Greater)     => self.iter_start(start, node.field().right(), cb),
-      Some(Equal)       => {
-        (*cb)(self, node)
-          .or_else(|:| self.iter_recur(node.field().right(), cb))
-      },
+  /// Record the path from the root to `node`, returning its depth, or `None`
+  /// if the tree's order does not lead there. Elements with keys equal to
+  /// `node`'s are walked in order, so this is O(log n + k) for k of them.
+  fn locate(&self, node: *mut T, path: &mut [PathElem<T>]) -> Result<Option<usize>, TreeError> {
+    let key = A::key(unsafe { &*node });
+    let mut depth = self.nsearch_path(|elem| self.cmp.compare(A::key(elem), key), path)?;
+    while let Some(i) = depth {
+      let cur = path[i].node;
+      if cur == node {
+        return Ok(Some(i))
+      }
+      if self.cmp.compare(A::key(unsafe { &*cur }), key) != Equal {
+        break
+      }
+      depth = path_next(path, i)?;
     }
+    Ok(None)
   }
 
-  pub fn iter<F, A>(&mut self, start: Option<&mut T>, cb: &mut F) -> Option<A>
-    where F: FnMut(&mut Self, *mut T) -> Option<A>
-  {
-    match start {
-      Some(start) => self.iter_start(start, self.root, cb),
-      None        => self.iter_recur(self.root, cb),
+  /// Record the path from the root to `node` by walking the whole tree in
+  /// order, comparing only addresses. This is O(n), for when keys can't be
+  /// trusted.
+  fn trace(&self, node: *mut T, path: &mut [PathElem<T>]) -> Result<Option<usize>, TreeError> {
+    let mut depth = self.first_path(path)?;
+    while let Some(i) = depth {
+      if path[i].node == node {
+        return Ok(Some(i))
+      }
+      depth = path_next(path, i)?;
     }
+    Ok(None)
   }
 
+  /// Record the path from the root to `node`, by key if possible and by
+  /// identity otherwise.
+  #[inline]
+  fn path_to(&self, node: *mut T, path: &mut [PathElem<T>]) -> Result<usize, TreeError> {
+    match self.locate(node, path)? {
+      Some(i) => Ok(i),
+      None    => self.trace(node, path)?.ok_or(TreeError::NotLinked),
+    }
+  }
 
-  fn reverse_iter_recur<F, A>(&mut self, node: *mut T, cb: &mut F) -> Option<A>
-    where F: FnMut(&mut Self, *mut T) -> Option<A>
-  {
-    if node == self.nil_ref() {
-      None
-    } else {
-      self.reverse_iter_recur(node.field().right(), cb)
-        .or_else(|:| (*cb)(self, node))
-        .or_else(|:| self.reverse_iter_recur(node.field().left, cb))
+  /// Record the path from the root to the first element, returning its depth.
+  fn first_path(&self, path: &mut [PathElem<T>]) -> Result<Option<usize>, TreeError> {
+    if self.root.is_null() {
+      return Ok(None)
+    }
+    path[0].node = self.root;
+    let mut i = 0;
+    loop {
+      let left = path[i].node.field().left();
+      if left.is_null() {
+        return Ok(Some(i))
+      }
+      ensure!(i + 1 < path.len());
+      path[i].cmp = Less;
+      i += 1;
+      path[i].node = left;
     }
   }
 
-  fn reverse_iter_start<F, A>(&mut self, start: &mut T, node: *mut T, cb: &mut F) -> Option<A>
-    where F: FnMut(&mut Self, *mut T) -> Option<A>
-  {
-    match (*start).partial_cmp(unsafe { &*node }) {
-      None | Some(Less) => {
-        self.reverse_iter_start(start, node.field().right(), cb)
-          .or_else(|:| (*cb)(self, node))
-          .or_else(|:| self.reverse_iter_recur(node.field().left, cb))
-      },
-      Some(Greater)     => self.reverse_iter_start(start, node.field().left, cb),
-      Some(Equal)       => {
-        (*cb)(self, node)
-          .or_else(|:| self.reverse_iter_recur(node.field().left, cb))
-      },
+  /// Record the path from the root to the last element, returning its depth.
+  fn last_path(&self, path: &mut [PathElem<T>]) -> Result<Option<usize>, TreeError> {
+    if self.root.is_null() {
+      return Ok(None)
+    }
+    path[0].node = self.root;
+    let mut i = 0;
+    loop {
+      let right = path[i].node.field().right();
+      if right.is_null() {
+        return Ok(Some(i))
+      }
+      ensure!(i + 1 < path.len());
+      path[i].cmp = Greater;
+      i += 1;
+      path[i].node = right;
     }
   }
 
-  pub fn reverse_iter<F, A>(&mut self, start: Option<&mut T>, cb: &mut F) -> Option<A>
-    where F: FnMut(&mut Self, *mut T) -> Option<A>
+  /// An element equal to `key`.
+  #[inline]
+  pub fn search<Q>(&self, key: &Q) -> Option<NonNull<T>>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
   {
-    match start {
-      Some(start) => self.reverse_iter_start(start, self.root, cb),
-      None        => self.reverse_iter_recur(self.root, cb),
-    }
+    NonNull::new(search_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key)))
   }
-}
 
-struct PathElem<T> {
+  /// The first element not less than `key`.
+  #[inline]
+  pub fn nsearch<Q>(&self, key: &Q) -> Option<NonNull<T>>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    NonNull::new(nsearch_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key)))
+  }
+
+  /// The last element not greater than `key`.
+  #[inline]
+  pub fn psearch<Q>(&self, key: &Q) -> Option<NonNull<T>>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    NonNull::new(psearch_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key)))
+  }
+
+  /// An element equal to `key`.
+  #[inline]
+  pub fn find<Q>(&self, key: &Q) -> Option<&T>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    self.find_by(|elem| self.cmp.compare(A::key(elem).borrow(), key))
+  }
+
+  /// The first element not less than `key`.
+  #[inline]
+  pub fn lower_bound<Q>(&self, key: &Q) -> Option<&T>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    self.lower_bound_by(|elem| self.cmp.compare(A::key(elem).borrow(), key))
+  }
+
+  /// The first element greater than `key`.
+  #[inline]
+  pub fn upper_bound<Q>(&self, key: &Q) -> Option<&T>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    self.upper_bound_by(|elem| self.cmp.compare(A::key(elem).borrow(), key))
+  }
+
+  /// The first and last elements equal to `key`, if there are any. Equal
+  /// elements are kept in the order they were inserted.
+  #[inline]
+  pub fn equal_range<Q>(&self, key: &Q) -> Option<(NonNull<T>, NonNull<T>)>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    let f = |elem: &T| self.cmp.compare(A::key(elem).borrow(), key);
+    let first = NonNull::new(nsearch_(self.root, f))?;
+    if f(unsafe { first.as_ref() }) != Equal {
+      return None
+    }
+    Some((first, NonNull::new(psearch_(self.root, f))?))
+  }
+
+  /// The number of elements equal to `key`, in O(log n + k) time.
+  pub fn count<Q>(&self, key: &Q) -> usize
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    let f = |elem: &T| self.cmp.compare(A::key(elem).borrow(), key);
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    let mut n = 0;
+    let mut depth = intact(self.nsearch_path(f, &mut path));
+    while let Some(i) = depth {
+      if f(unsafe { &*path[i].node }) != Equal {
+        break
+      }
+      n += 1;
+      depth = intact(path_next(&mut path, i));
+    }
+    n
+  }
+
+  /// The element for which `f` returns `Equal`. `f` reports how an element
+  /// compares to the target, and must agree with the tree's order.
+  #[inline]
+  pub fn find_by<F>(&self, f: F) -> Option<&T> where F: FnMut(&T) -> Ordering {
+    unsafe { search_(self.root, f).as_ref() }
+  }
+
+  /// The first element for which `f` does not return `Less`.
+  #[inline]
+  pub fn lower_bound_by<F>(&self, f: F) -> Option<&T> where F: FnMut(&T) -> Ordering {
+    unsafe { nsearch_(self.root, f).as_ref() }
+  }
+
+  /// The first element for which `f` returns `Greater`.
+  #[inline]
+  pub fn upper_bound_by<F>(&self, f: F) -> Option<&T> where F: FnMut(&T) -> Ordering {
+    unsafe { usearch_(self.root, f).as_ref() }
+  }
+
+  /// The first element whose own summary `f` holds for, found by descending
+  /// on its subtrees' summaries rather than keys, in O(log n) time.
+  ///
+  /// `f` must hold for a subtree's summary if and only if it holds for that
+  /// of some element in it, as with a maximum against a bound: subtrees it
+  /// fails for are passed over, and one it holds for is never backed out of.
+  /// A sum against a bound does not qualify, as elements each too small may
+  /// add up to enough, and then nothing may be found though a later element
+  /// would do.
+  #[inline]
+  pub fn find_first_where<F>(&self, f: F) -> Option<&T> where F: FnMut(S) -> bool {
+    unsafe { find_first_where_(self.root, f).as_ref() }
+  }
+
+
+  /// Record the path from the root to the leaf where `node` belongs. If
+  /// `unique`, stop at any element with an equal key instead.
+  #[inline]
+  fn wind(&self, node: *mut T, path: &mut [PathElem<T>], unique: bool) -> Result<Slot, TreeError> {
+    path[0].node = self.root;
+    let mut i = 0;
+    while !path[i].node.is_null() {
+      let cur = path[i].node;
+      if cur == node {
+        return Err(TreeError::DuplicateKey)
+      }
+      ensure!(i + 1 < path.len());
+      // Equal elements go after those already present, keeping insertion
+      // order.
+      path[i].cmp = match self.order(unsafe { &*node }, unsafe { &*cur }) {
+        Less            => Less,
+        Equal if unique => return Ok(Slot::Occupied(i)),
+        Greater | Equal => Greater,
+      };
+      path[i + 1].node = match path[i].cmp {
+        Less => cur.field().left(),
+        _    => cur.field().right(),
+      };
+      i += 1;
+    }
+    Ok(Slot::Vacant(i))
+  }
+
+  /// Link `node` into the tree, after any elements with an equal key.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not already be linked into a tree, and must stay put and
+  /// alive until it is removed.
+  #[inline]
+  pub unsafe fn insert(&mut self, node: NonNull<T>) {
+    self.try_insert(node).unwrap_or_else(|err| panic!("{}", err))
+  }
+
+  /// Link `node` into the tree, after any elements with an equal key, or
+  /// report why it can't be.
+  ///
+  /// `node` is looked for among the elements with an equal key first, so
+  /// this takes O(log n + k) time for k of them.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not be linked into another tree, and must stay put and
+  /// alive until it is removed.
+  pub unsafe fn try_insert(&mut self, node: NonNull<T>) -> Result<(), TreeError> {
+    let node = node.as_ptr();
+    if self.order(&*node, &*node) != Equal {
+      return Err(TreeError::IncomparableKey)
+    }
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    if self.locate(node, &mut path)?.is_some() {
+      return Err(TreeError::DuplicateKey)
+    }
+    match self.wind(node, &mut path, false)? {
+      Slot::Vacant(i)   => self.link(&mut path, i, node),
+      Slot::Occupied(_) => unreachable!(),
+    }
+    Ok(())
+  }
+
+  /// Link `node` into the tree unless an element with an equal key is
+  /// already present, in which case that element is returned instead.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`.
+  #[inline]
+  pub unsafe fn insert_unique(&mut self, node: NonNull<T>) -> Result<(), NonNull<T>> {
+    let node = node.as_ptr();
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    match self.wind(node, &mut path, true).unwrap_or_else(|err| panic!("{}", err)) {
+      Slot::Vacant(i)   => {
+        self.link(&mut path, i, node);
+        Ok(())
+      },
+      Slot::Occupied(i) => Err(NonNull::new_unchecked(path[i].node)),
+    }
+  }
+
+  /// Link `node` into the tree. If an element with an equal key is already
+  /// present, `node` takes over its exact position and the evicted element is
+  /// returned, unlinked.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`.
+  #[inline]
+  pub unsafe fn insert_or_replace(&mut self, node: NonNull<T>) -> Option<NonNull<T>> {
+    let node = node.as_ptr();
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    match self.wind(node, &mut path, true).unwrap_or_else(|err| panic!("{}", err)) {
+      Slot::Vacant(i)   => {
+        self.link(&mut path, i, node);
+        None
+      },
+      Slot::Occupied(i) => {
+        let old = path[i].node;
+        self.replace_at(&path, i, node);
+        Some(NonNull::new_unchecked(old))
+      },
+    }
+  }
+
+  /// Put `node` in place of `path[i].node`, with the same links and color.
+  /// The evicted element is left as unlinked as a new one.
+  #[inline]
+  unsafe fn replace_at(&mut self, path: &[PathElem<T>], i: usize, node: *mut T) {
+    let old = path[i].node;
+    node.field().reset(old.field().color());
+    node.field().set_left(old.field().left());
+    node.field().set_right(old.field().right());
+    update(node);
+    self.relink(path, i, node);
+    update_path(path, i);
+    old.field().reset(false);
+    old.field().summary.set(S::EMPTY);
+  }
+
+  /// Link `node` in at the leaf `path[i]`, given the path from the root to it,
+  /// and rebalance.
+  #[inline]
+  unsafe fn link(&mut self, path: &mut [PathElem<T>], i: usize, node: *mut T) {
+    node.field().reset(true);
+    graft(path, i, node);
+    self.root = path[0].node;
+    self.len.add(1);
+  }
+
+  /// Point whichever child link of `path[i - 1]` leads to `path[i]` at
+  /// `node`, or make `node` the root if `i` is the top of the path.
+  #[inline]
+  fn relink(&mut self, path: &[PathElem<T>], i: usize, node: *mut T) {
+    if i == 0 {
+      self.root = node;
+    } else if path[i - 1].cmp == Less {
+      path[i - 1].node.field().set_left(node);
+    } else {
+      path[i - 1].node.field().set_right(node);
+    }
+  }
+
+  /// Unlink `node` from the tree. It is found by identity, even among
+  /// elements with equal keys.
+  ///
+  /// # Safety
+  ///
+  /// `node` must be linked into this tree.
+  pub unsafe fn remove(&mut self, node: NonNull<T>) {
+    self.try_remove(node).unwrap_or_else(|err| panic!("{}", err))
+  }
+
+  /// Unlink `node` from the tree, or report why it can't be.
+  ///
+  /// `node` is found by key first, and failing that by walking the whole tree
+  /// comparing addresses, so it is unlinked correctly even if its key was
+  /// changed while linked or the comparator is inconsistent. To avoid the
+  /// search altogether, remove through an `OccupiedEntry`, which already
+  /// holds the path to its element.
+  ///
+  /// # Safety
+  ///
+  /// `node` must point to a live element, though it need not be linked into
+  /// this tree.
+  pub unsafe fn try_remove(&mut self, node: NonNull<T>) -> Result<(), TreeError> {
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    let nodep = self.path_to(node.as_ptr(), &mut path)?;
+    self.unlink_at(&mut path, nodep)
+  }
+
+  /// Unlink `path[nodep].node` as `unlink_at` does, then record the path to
+  /// the element that followed it, returning its depth. Unlike finding it
+  /// again by key, this is O(log n) however many elements have equal keys.
+  unsafe fn unlink_before(&mut self, path: &mut [PathElem<T>], nodep: usize) -> Result<Option<usize>, TreeError> {
+    // The successor is the first element of node's right subtree, which is
+    // swapped into node's place, or else the nearest ancestor node is left of.
+    let succp = match path[nodep].node.field().right().is_null() {
+      false => Some(nodep),
+      true  => path[..nodep].iter().rposition(|elem| elem.cmp == Less),
+    };
+    let mut old = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    old[..=nodep].copy_from_slice(&path[..=nodep]);
+    self.unlink_at(&mut old, nodep)?;
+    let succ = match succp {
+      Some(j) => old[j].node,
+      None    => return Ok(None),
+    };
+    // Rebalancing only lifts nodes from beside the old path to just above
+    // the node on it they hung from, which keeps its child on the path on
+    // the same side. So each node met on the way down is either the next on
+    // the old path, or was lifted from beside it, and the way on is the same.
+    let mut k = 0;
+    let mut i = 0;
+    path[0].node = self.root;
+    while path[i].node != succ {
+      ensure!(!path[i].node.is_null() && i + 1 < path.len() && k < old.len());
+      let cur = path[i].node;
+      path[i].cmp = old[k].cmp;
+      if cur == old[k].node {
+        k += 1;
+      }
+      path[i + 1].node = match path[i].cmp {
+        Less => cur.field().left(),
+        _    => cur.field().right(),
+      };
+      i += 1;
+    }
+    Ok(Some(i))
+  }
+
+  /// Unlink `path[nodep].node`, given the path from the root to it. It is
+  /// left as unlinked as a new element.
+  unsafe fn unlink_at(&mut self, path: &mut [PathElem<T>], nodep: usize) -> Result<(), TreeError> {
+    let node = path[nodep].node;
+    self.prune(path, nodep)?;
+    node.field().reset(false);
+    node.field().summary.set(S::EMPTY);
+    self.len.sub(1);
+    Ok(())
+  }
+
+  /// Splice `path[nodep].node` out of the tree and rebalance, leaving its own
+  /// links stale.
+  unsafe fn prune(&mut self, path: &mut [PathElem<T>], nodep: usize) -> Result<(), TreeError> {
+    let node = path[nodep].node;
+
+    // Find node's successor, in preparation for swap.
+    ensure!(nodep + 1 < path.len());
+    path[nodep].cmp = Greater;
+    path[nodep + 1].node = node.field().right();
+    let mut i = nodep + 1;
+    while !path[i].node.is_null() {
+      ensure!(i + 1 < path.len());
+      path[i].cmp = Less;
+      path[i + 1].node = path[i].node.field().left();
+      i += 1;
+    }
+
+    i -= 1;
+    if path[i].node != node {
+      // Swap node with its successor.
+      let succ = path[i].node;
+      let tred = succ.field().color();
+      succ.field().set_color(node.field().color());
+      succ.field().set_left(node.field().left());
+      // If node's successor is its right child, the following code will do
+      // the wrong thing for the right child pointer.  However, it doesn't
+      // matter, because the pointer will be properly set when the successor
+      // is pruned.
+      succ.field().set_right(node.field().right());
+      node.field().set_color(tred);
+      // The pruned leaf node's child pointers are reset by `unlink_at` once
+      // it is out, so don't bother setting them to null here.
+      path[nodep].node = succ;
+      path[i].node = node;
+      self.relink(path, nodep, succ);
+    } else {
+      let left = node.field().left();
+      if !left.is_null() {
+        // node has no successor, but it has a left child.
+        // Splice node out, without losing the left child.
+        ensure!(!node.field().color());
+        ensure!(left.field().color());
+        left.field().set_color(false);
+        self.relink(path, i, left);
+        update_path(path, i);
+        return Ok(())
+      } else if i == 0 {
+        // The tree only contained one node
+        self.root = ptr::null_mut();
+        return Ok(())
+      }
+    }
+    if path[i].node.field().color() {
+      // Prune red node, which reqires no fixup
+      ensure!(path[i - 1].cmp == Less);
+      path[i - 1].node.field().set_left(ptr::null_mut());
+      update_path(path, i);
+      return Ok(())
+    }
+
+    // The node to be pruned is black, so unwind until balance is restored.
+    // The path is left as it was, holding the new root of each subtree in
+    // `sub` instead, for `unlink_before`.
+    let mut sub = ptr::null_mut();
+    while i > 0 {
+      i -= 1;
+      let cnode = path[i].node;
+      let child = mem::replace(&mut sub, cnode);
+      match path[i].cmp {
+        Equal   => return Err(TreeError::CorruptLinks),
+        Less    => {
+          cnode.field().set_left(child);
+          if cnode.field().color() {
+            let right = cnode.field().right();
+            let right_left = right.field().left();
+            let tnode = if is_red(right_left) {
+              // In the following diagrams, ||, //, and \\
+              // indicate the path to the removed node.
+              //
+              //      ||
+              //    pathp(r)
+              //  //        \
+              // (b)        (b)
+              //           /
+              //          (r)
+              //
+              cnode.field().set_color(false);
+              cnode.field().set_right(right.rotate_right());
+              cnode.rotate_left()
+            } else {
+              //      ||
+              //    pathp(r)
+              //  //        \
+              // (b)        (b)
+              //           /
+              //          (b)
+              //
+              cnode.rotate_left()
+            };
+            // Balance restored, but rotation modified subtree
+            // root.
+            ensure!(i > 0);
+            self.relink(path, i, tnode);
+            update_path(path, i);
+            return Ok(())
+          } else {
+            let right = cnode.field().right();
+            let right_left = right.field().left();
+            if is_red(right_left) {
+              //      ||
+              //    pathp(b)
+              //  //        \
+              // (b)        (b)
+              //           /
+              //          (r)
+              right_left.field().set_color(false);
+              cnode.field().set_right(right.rotate_right());
+              let tnode = cnode.rotate_left();
+              // Balance restored, but rotation modified
+              // subtree root, which may actually be the tree
+              // root.
+              self.relink(path, i, tnode);
+              update_path(path, i);
+              return Ok(())
+            } else {
+              //      ||
+              //    pathp(b)
+              //  //        \
+              // (b)        (b)
+              //           /
+              //          (b)
+              cnode.field().set_color(true);
+              sub = cnode.rotate_left();
+            }
+          }
+        },
+        Greater => {
+          cnode.field().set_right(child);
+          let left = cnode.field().left();
+          if left.field().color() {
+            let tnode;
+            let left_right = left.field().right();
+            let left_right_left = left_right.field().left();
+            if is_red(left_right_left) {
+              //      ||
+              //    pathp(b)
+              //   /        \\
+              // (r)        (b)
+              //   \
+              //   (b)
+              //   /
+              // (r)
+              left_right_left.field().set_color(false);
+              let unode = cnode.rotate_right();
+              unode.field().set_right(cnode.rotate_right());
+              tnode = unode.rotate_left();
+            } else {
+              //      ||
+              //    pathp(b)
+              //   /        \\
+              // (r)        (b)
+              //   \
+              //   (b)
+              //   /
+              // (b)
+              ensure!(!left_right.is_null());
+              left_right.field().set_color(true);
+              tnode = cnode.rotate_right();
+              tnode.field().set_color(false);
+            }
+            // Balance restored, but rotation modified subtree
+            // root, which may actually be the tree root.
+            self.relink(path, i, tnode);
+            update_path(path, i);
+            return Ok(())
+          } else if cnode.field().color() {
+            let left_left = left.field().left();
+            if is_red(left_left) {
+              //        ||
+              //      pathp(r)
+              //     /        \\
+              //   (b)        (b)
+              //   /
+              // (r)
+              cnode.field().set_color(false);
+              left.field().set_color(true);
+              left_left.field().set_color(false);
+              let tnode = cnode.rotate_right();
+              // Balance restored, but rotation modified
+              // subtree root.
+              ensure!(i > 0);
+              self.relink(path, i, tnode);
+              update_path(path, i);
+              return Ok(())
+            } else {
+              //        ||
+              //      pathp(r)
+              //     /        \\
+              //   (b)        (b)
+              //   /
+              // (b)
+              left.field().set_color(true);
+              cnode.field().set_color(false);
+              // Balance restored.
+              update_path(path, i + 1);
+              return Ok(())
+            }
+          } else {
+            let left_left = left.field().left();
+            if is_red(left_left) {
+              //               ||
+              //             pathp(b)
+              //            /        \\
+              //          (b)        (b)
+              //          /
+              //        (r)
+              left_left.field().set_color(false);
+              let tnode = cnode.rotate_right();
+              // Balance restored, but rotation modified
+              // subtree root, which may actually be the tree
+              // root.
+              self.relink(path, i, tnode);
+              update_path(path, i);
+              return Ok(())
+            } else {
+              //               ||
+              //             pathp(b)
+              //            /        \\
+              //          (b)        (b)
+              //          /
+              //        (b)
+              left.field().set_color(true);
+            }
+          }
+        },
+      }
+      update(sub);
+    }
+    // Set root
+    self.root = sub;
+    ensure!(!self.root.field().color());
+    Ok(())
+  }
+
+
+  /// Call `cb` on each element in order, beginning at the first not less than
+  /// `start` if given, until it returns `Some`. Unlike `iter`, `cb` gets the
+  /// tree too, shared so that it can look but not link or unlink.
+  ///
+  /// This does not recurse: the walk is kept in a `2 * usize::BITS` element
+  /// path on the stack, 2 KiB on 64-bit targets, however big the tree.
+  pub fn walk<F, R>(&self, start: Option<&A::Key>, cb: &mut F) -> Option<R>
+    where F: FnMut(&Self, NonNull<T>) -> Option<R>
+  {
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    let depth = match start {
+      Some(start) => self.nsearch_path(|elem| self.cmp.compare(A::key(elem), start), &mut path),
+      None        => self.first_path(&mut path),
+    };
+    let mut depth = intact(depth);
+    while let Some(i) = depth {
+      let ret = cb(self, unsafe { NonNull::new_unchecked(path[i].node) });
+      if ret.is_some() {
+        return ret
+      }
+      depth = intact(path_next(&mut path, i));
+    }
+    None
+  }
+
+  /// Call `cb` on each element in reverse order, beginning at the last not
+  /// greater than `start` if given, until it returns `Some`. Stack use is as
+  /// for `walk`.
+  pub fn reverse_walk<F, R>(&self, start: Option<&A::Key>, cb: &mut F) -> Option<R>
+    where F: FnMut(&Self, NonNull<T>) -> Option<R>
+  {
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    let depth = match start {
+      Some(start) => self.psearch_path(|elem| self.cmp.compare(A::key(elem), start), &mut path),
+      None        => self.last_path(&mut path),
+    };
+    let mut depth = intact(depth);
+    while let Some(i) = depth {
+      let ret = cb(self, unsafe { NonNull::new_unchecked(path[i].node) });
+      if ret.is_some() {
+        return ret
+      }
+      depth = intact(path_prev(&mut path, i));
+    }
+    None
+  }
+}
+
+impl<T, A, C> Tree<T, A, C, Size> where T: Intrusive<Node<T, Size>>
+{
+  /// The number of elements, in O(1) time, from the size of the root's
+  /// subtree.
+  #[inline]
+  pub fn len(&self) -> usize {
+    summary::<T, Node<T, Size>>(self.root).0
+  }
+}
+
+// Order statistics, for trees whose nodes keep the size of their subtrees.
+impl<T, A, C, L> Tree<T, A, C, Size, L>
+  where T: Intrusive<Node<T, Size>>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+
+  /// The element with `k` elements before it, in O(log n) time.
+  #[inline]
+  pub fn select(&self, k: usize) -> Option<NonNull<T>> {
+    NonNull::new(select_(self.root, k))
+  }
+
+  /// The number of elements less than `key`, in O(log n) time.
+  #[inline]
+  pub fn rank<Q>(&self, key: &Q) -> usize
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    rank_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key) == Less)
+  }
+}
+
+struct PathElem<T> {
   node: *mut T,
-  cmp:  Option<Ordering>,
+  cmp:  Ordering,
+}
+
+impl<T> Clone for PathElem<T> {
+  #[inline]
+  fn clone(&self) -> PathElem<T> {
+    *self
+  }
+}
+
+impl<T> Copy for PathElem<T> { }
+
+impl<T> PathElem<T> {
+  #[inline]
+  fn new(node: *mut T) -> PathElem<T> {
+    PathElem { node, cmp: Equal }
+  }
+}
+
+/// Put the red `node` at `path[i]`, in place of a subtree of the same black
+/// height, and rebalance. The root ends up at `path[0]`; returns whether the
+/// black height grew.
+unsafe fn graft<T, S>(path: &mut [PathElem<T>], mut i: usize, node: *mut T) -> bool
+  where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  path[i].node = node;
+  update(node);
+
+  // Unwind
+  while i > 0 {
+    i -= 1;
+    let mut cnode = path[i].node;
+    if path[i].cmp == Less {
+      let left = path[i + 1].node;
+      cnode.field().set_left(left);
+      if !is_red(left) {
+        update_path(path, i + 1);
+        return false
+      }
+      let left_left = left.field().left();
+      if is_red(left_left) {
+        // Fix up 4-node
+        left_left.field().set_color(false);
+        cnode = cnode.rotate_right();
+      }
+    } else {
+      let right = path[i + 1].node;
+      cnode.field().set_right(right);
+      if !is_red(right) {
+        update_path(path, i + 1);
+        return false
+      }
+      let left = cnode.field().left();
+      if is_red(left) {
+        // Split 4-node
+        left.field().set_color(false);
+        right.field().set_color(false);
+        cnode.field().set_color(true);
+      } else {
+        // Lean left
+        let tred = cnode.field().color();
+        let tnode = cnode.rotate_left();
+        tnode.field().set_color(tred);
+        cnode.field().set_color(true);
+        cnode = tnode;
+      }
+    }
+    update(cnode);
+    path[i].node = cnode;
+  }
+
+  // Paint the root black
+  let root = path[0].node;
+  let grew = root.field().color();
+  root.field().set_color(false);
+  grew
+}
+
+/// Step a path ending at depth `i` to the in-order successor, returning its
+/// depth.
+fn path_next<T, S>(path: &mut [PathElem<T>], mut i: usize) -> Result<Option<usize>, TreeError>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  let right = path[i].node.field().right();
+  if !right.is_null() {
+    ensure!(i + 1 < path.len());
+    path[i].cmp = Greater;
+    i += 1;
+    path[i].node = right;
+    loop {
+      let left = path[i].node.field().left();
+      if left.is_null() {
+        return Ok(Some(i))
+      }
+      ensure!(i + 1 < path.len());
+      path[i].cmp = Less;
+      i += 1;
+      path[i].node = left;
+    }
+  }
+  while i > 0 {
+    i -= 1;
+    if path[i].cmp == Less {
+      return Ok(Some(i))
+    }
+  }
+  Ok(None)
+}
+
+/// Step a path ending at depth `i` to the in-order predecessor, returning its
+/// depth.
+fn path_prev<T, S>(path: &mut [PathElem<T>], mut i: usize) -> Result<Option<usize>, TreeError>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  let left = path[i].node.field().left();
+  if !left.is_null() {
+    ensure!(i + 1 < path.len());
+    path[i].cmp = Less;
+    i += 1;
+    path[i].node = left;
+    loop {
+      let right = path[i].node.field().right();
+      if right.is_null() {
+        return Ok(Some(i))
+      }
+      ensure!(i + 1 < path.len());
+      path[i].cmp = Greater;
+      i += 1;
+      path[i].node = right;
+    }
+  }
+  while i > 0 {
+    i -= 1;
+    if path[i].cmp == Greater {
+      return Ok(Some(i))
+    }
+  }
+  Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+  use alloc::vec::Vec;
+  use core::cmp::Ordering::Less;
+  use core::ptr::NonNull;
+
+  use crate::intrusive::{Intrusive, IntrusiveExt};
+  use super::interval::{IntervalAdapter, IntervalTree, MaxEnd};
+  use super::parent::ParentNode;
+  use super::{is_red, Augment, Entry, KeyAdapter, Links, Natural, Node, Size, Tree, TreeError};
+
+  /// An element that can be linked into either kind of tree, so that their
+  /// tests share it.
+  pub(super) struct Elem {
+    pub(super) key:    u32,
+    pub(super) rev:    u32,
+    pub(super) node:   Node<Elem>,
+    pub(super) parent: ParentNode<Elem>,
+  }
+
+  impl Intrusive<Node<Elem>> for Elem {
+    fn field(&self) -> &Node<Elem> {
+      &self.node
+    }
+  }
+
+  impl Intrusive<ParentNode<Elem>> for Elem {
+    fn field(&self) -> &ParentNode<Elem> {
+      &self.parent
+    }
+  }
+
+  pub(super) struct ByKey;
+
+  impl KeyAdapter<Elem> for ByKey {
+    type Key = u32;
+
+    fn key(node: &Elem) -> &u32 {
+      &node.key
+    }
+  }
+
+  struct ByRev;
+
+  impl KeyAdapter<Elem> for ByRev {
+    type Key = u32;
+
+    fn key(node: &Elem) -> &u32 {
+      &node.rev
+    }
+  }
+
+  pub(super) fn make(keys: &[u32]) -> Vec<Elem> {
+    keys.iter().map(|&key| Elem { key, rev: !key, node: Node::new(), parent: ParentNode::new() }).collect()
+  }
+
+  pub(super) fn ptr(elem: &Elem) -> NonNull<Elem> {
+    NonNull::from(elem)
+  }
+
+  pub(super) fn key(ptr: NonNull<Elem>) -> u32 {
+    unsafe { ptr.as_ref() }.key
+  }
+
+  /// Check the red-black invariants of the subtree at `node`, collecting its
+  /// keys in order and calling `each` on every node for those particular to
+  /// the kind of tree. Returns the black height.
+  pub(super) fn check_subtree<L>(node: *mut Elem, keys: &mut Vec<u32>, each: &mut dyn FnMut(*mut Elem)) -> usize
+    where Elem: Intrusive<L>, L: Links<Elem>
+  {
+    if node.is_null() {
+      return 1
+    }
+    each(node);
+    let links: &L = node.field();
+    let (left, right) = (links.left(), links.right());
+    if links.color() {
+      assert!(!is_red::<_, L>(left) && !is_red::<_, L>(right), "consecutive red links");
+    }
+    let left_height = check_subtree::<L>(left, keys, each);
+    keys.push(unsafe { &*node }.key);
+    let right_height = check_subtree::<L>(right, keys, each);
+    assert_eq!(left_height, right_height, "unbalanced black height");
+    left_height + !links.color() as usize
+  }
+
+  fn check(tree: &Tree<Elem, ByKey>) -> Vec<u32> {
+    let mut keys = Vec::new();
+    assert!(!is_red::<_, Node<Elem>>(tree.root), "red root");
+    let left_leaning = &mut |node: *mut Elem| {
+      assert!(!is_red::<_, Node<Elem>>(unsafe { &*node }.node.right()), "right-leaning red link")
+    };
+    check_subtree::<Node<Elem>>(tree.root, &mut keys, left_leaning);
+    assert!(keys.windows(2).all(|w| w[0] <= w[1]), "out of order");
+    keys
+  }
+
+  /// Scramble `0..n` deterministically.
+  pub(super) fn shuffled(n: u32) -> Vec<u32> {
+    let mut keys: Vec<u32> = (0..n).collect();
+    let mut state = 0x2545_f491_u32;
+    for i in (1..keys.len()).rev() {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      keys.swap(i, state as usize % (i + 1));
+    }
+    keys
+  }
+
+  #[test]
+  fn insert_remove() {
+    let keys = shuffled(500);
+    let elems = make(&keys);
+    let mut tree = build(&elems);
+    assert_eq!(check(&tree), (0..500).collect::<Vec<_>>());
+
+    for elem in elems.iter().step_by(2) {
+      unsafe { tree.remove(ptr(elem)) };
+      check(&tree);
+    }
+    let mut left: Vec<u32> = keys.iter().skip(1).step_by(2).cloned().collect();
+    left.sort();
+    assert_eq!(check(&tree), left);
+
+    for elem in elems.iter().skip(1).step_by(2) {
+      unsafe { tree.remove(ptr(elem)) };
+    }
+    assert!(tree.first().is_none());
+  }
+
+  fn build(elems: &[Elem]) -> Tree<Elem, ByKey> {
+    let mut tree = Tree::new();
+    for elem in elems {
+      unsafe { tree.insert(ptr(elem)) };
+    }
+    tree
+  }
+
+  #[test]
+  fn moved() {
+    let elems = make(&shuffled(100));
+    let trees = [build(&elems[..50]), build(&elems[50..])];
+    for tree in &trees {
+      assert_eq!(check(tree).len(), 50);
+    }
+  }
+
+  #[test]
+  fn adapters() {
+    let elems = make(&shuffled(10));
+    let mut tree = Tree::<Elem, ByRev>::new();
+    for elem in &elems {
+      unsafe { tree.insert(ptr(elem)) };
+    }
+    assert_eq!(tree.first().map(key), Some(9));
+    assert_eq!(tree.search(&!3).map(key), Some(3));
+  }
+
+  #[test]
+  fn lookup() {
+    let elems = make(&[40, 10, 30, 20]);
+    let tree = build(&elems);
+    assert_eq!(tree.find(&30).map(|e| e.key), Some(30));
+    assert!(tree.find(&25).is_none());
+    assert_eq!(tree.lower_bound(&20).map(|e| e.key), Some(20));
+    assert_eq!(tree.lower_bound(&25).map(|e| e.key), Some(30));
+    assert_eq!(tree.upper_bound(&20).map(|e| e.key), Some(30));
+    assert!(tree.upper_bound(&40).is_none());
+    assert_eq!(tree.find_by(|e| (e.key / 10).cmp(&1)).map(|e| e.key), Some(10));
+  }
+
+  #[test]
+  fn comparator() {
+    let elems = make(&shuffled(20));
+    let modulus = 7;
+    let by_residue = move |a: &u32, b: &u32| (a % modulus, a).cmp(&(b % modulus, b));
+    let mut tree = Tree::<Elem, ByKey, _>::with_comparator(by_residue);
+    for elem in &elems {
+      unsafe { tree.insert(ptr(elem)) };
+    }
+    assert_eq!(tree.first().map(key), Some(0));
+    assert_eq!(unsafe { tree.next(tree.first().unwrap()) }.map(key), Some(7));
+    assert_eq!(tree.last().map(key), Some(13));
+    assert_eq!(tree.find(&15).map(|e| e.key), Some(15));
+    assert_eq!(tree.upper_bound(&14).map(|e| e.key), Some(1));
+  }
+
+  fn run(tree: &Tree<Elem, ByKey>, key: u32) -> Vec<NonNull<Elem>> {
+    let mut run = Vec::new();
+    if let Some((first, last)) = tree.equal_range(&key) {
+      let mut cur = first;
+      run.push(cur);
+      while cur != last {
+        cur = unsafe { tree.next(cur) }.unwrap();
+        run.push(cur);
+      }
+    }
+    run
+  }
+
+  #[test]
+  fn duplicates() {
+    let keys: Vec<u32> = shuffled(60).iter().map(|k| k / 4).collect();
+    let elems = make(&keys);
+    let mut tree = build(&elems);
+    check(&tree);
+    assert_eq!(tree.count(&3), 4);
+    let mut threes: Vec<_> = elems.iter().filter(|e| e.key == 3).map(ptr).collect();
+    assert_eq!(run(&tree, 3), threes);
+
+    unsafe { tree.remove(threes.remove(1)) };
+    check(&tree);
+    assert_eq!(run(&tree, 3), threes);
+    assert!(tree.equal_range(&15).is_none());
+
+    let same = make(&[7; 100]);
+    let mut tree = build(&same);
+    for (removed, i) in shuffled(100).into_iter().enumerate() {
+      assert_eq!(tree.count(&7), 100 - removed);
+      unsafe { tree.remove(ptr(&same[i as usize])) };
+      check(&tree);
+    }
+    assert!(tree.first().is_none());
+  }
+
+  #[test]
+  fn unique() {
+    let elems = make(&shuffled(50));
+    let mut tree = Tree::new();
+    for elem in &elems {
+      assert_eq!(unsafe { tree.insert_unique(ptr(elem)) }, Ok(()));
+    }
+    let extra = make(&[20, 20, 50]);
+    assert_eq!(unsafe { tree.insert_unique(ptr(&extra[0])) }.map_err(key), Err(20));
+    assert_eq!(check(&tree).len(), 50);
+
+    let old = tree.search(&20).unwrap();
+    assert_eq!(unsafe { tree.insert_or_replace(ptr(&extra[1])) }, Some(old));
+    assert_eq!(tree.search(&20), Some(ptr(&extra[1])));
+    let links = &unsafe { old.as_ref() }.node;
+    assert!(links.left().is_null() && links.right().is_null());
+    assert_eq!(unsafe { tree.insert_or_replace(ptr(&extra[2])) }, None);
+    assert_eq!(check(&tree), (0..51).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn entry() {
+    let elems = make(&(0..40).map(|k| k * 2).collect::<Vec<_>>());
+    let mut tree = build(&elems);
+    let extra = make(&[31, 32]);
+
+    match tree.entry(&31) {
+      Entry::Vacant(entry) => assert_eq!(unsafe { entry.insert(ptr(&extra[0])) }.key, 31),
+      Entry::Occupied(_)   => panic!("31 is not present"),
+    }
+    assert_eq!(unsafe { tree.entry(&31).or_insert(ptr(&extra[1])) }.key, 31);
+    check(&tree);
+
+    match tree.entry(&32) {
+      Entry::Occupied(entry) => {
+        assert_eq!(entry.get().key, 32);
+        assert_eq!(unsafe { entry.replace(ptr(&extra[1])) }, ptr(&elems[16]));
+      },
+      Entry::Vacant(_)       => panic!("32 is present"),
+    }
+    assert_eq!(tree.search(&32), Some(ptr(&extra[1])));
+
+    for k in [0, 31, 32, 78] {
+      match tree.entry(&k) {
+        Entry::Occupied(entry) => assert_eq!(key(entry.remove()), k),
+        Entry::Vacant(_)       => panic!("{} is present", k),
+      }
+      check(&tree);
+    }
+    assert_eq!(check(&tree).len(), 37);
+  }
+
+  #[test]
+  fn unlink_mutated() {
+    let mut elems = make(&shuffled(20));
+    let base = elems.as_mut_ptr();
+    let at = |i: usize| unsafe { NonNull::new_unchecked(base.add(i)) };
+    let mut tree = Tree::<Elem, ByKey>::new();
+    for i in 0..20 {
+      unsafe { tree.insert(at(i)) };
+    }
+    unsafe { (*base.add(3)).key = 1000 };
+    assert_eq!(unsafe { tree.try_remove(at(3)) }, Ok(()));
+    assert_eq!(unsafe { tree.try_remove(at(3)) }, Err(TreeError::NotLinked));
+    assert_eq!(check(&tree).len(), 19);
+  }
+
+  #[test]
+  fn fallible() {
+    let elems = make(&[1, 2, 13, 20]);
+    let cmp = |a: &u32, b: &u32| if *a == 13 { Less } else { a.cmp(b) };
+    let mut tree = Tree::<Elem, ByKey, _>::with_comparator(cmp);
+    for elem in &elems[..2] {
+      assert_eq!(unsafe { tree.try_insert(ptr(elem)) }, Ok(()));
+    }
+    assert_eq!(unsafe { tree.try_insert(ptr(&elems[1])) }, Err(TreeError::DuplicateKey));
+    assert_eq!(unsafe { tree.try_insert(ptr(&elems[2])) }, Err(TreeError::IncomparableKey));
+    assert_eq!(unsafe { tree.try_next(ptr(&elems[0])) }.map(|n| n.map(key)), Ok(Some(2)));
+    assert_eq!(unsafe { tree.try_prev(ptr(&elems[3])) }, Err(TreeError::NotLinked));
+    assert_eq!(unsafe { tree.try_remove(ptr(&elems[3])) }, Err(TreeError::NotLinked));
+    assert_eq!(unsafe { tree.try_remove(ptr(&elems[0])) }, Ok(()));
+  }
+
+  #[test]
+  fn fallible_unlinked() {
+    let elems = make(&(1..=7).collect::<Vec<_>>());
+    let mut tree = build(&elems);
+    let others = make(&[1, 2, 3]);
+    let other = build(&others);
+    assert_eq!(unsafe { tree.try_remove(ptr(&elems[1])) }, Ok(()));
+    let links = &elems[1].node;
+    assert!(links.left().is_null() && links.right().is_null() && !links.color());
+    assert_eq!(unsafe { tree.try_next(ptr(&elems[1])) }, Err(TreeError::NotLinked));
+    assert_eq!(unsafe { tree.try_prev(ptr(&elems[1])) }, Err(TreeError::NotLinked));
+
+    // Linked into another tree, with children of its own there
+    let root = NonNull::new(other.root).unwrap();
+    assert!(!unsafe { root.as_ref() }.node.left().is_null());
+    assert_eq!(unsafe { tree.try_next(root) }, Err(TreeError::NotLinked));
+    assert_eq!(unsafe { tree.try_prev(root) }, Err(TreeError::NotLinked));
+    assert_eq!(unsafe { other.try_next(root) }.map(|n| n.map(key)), Ok(Some(3)));
+  }
+
+  #[test]
+  fn fallible_equal_keys() {
+    let elems = make(&[5; 8]);
+    let mut tree = build(&elems);
+    for elem in &elems {
+      assert_eq!(unsafe { tree.try_insert(ptr(elem)) }, Err(TreeError::DuplicateKey));
+    }
+    assert_eq!(check(&tree).len(), 8);
+  }
+
+  #[test]
+  fn fallible_corrupt() {
+    let elems = make(&[0, 1, 2]);
+    let mut tree = build(&elems);
+    // A cycle back up to the root
+    elems[0].node.set_left(tree.root);
+    assert_eq!(unsafe { tree.try_next(ptr(&elems[0])) }, Err(TreeError::CorruptLinks));
+    assert_eq!(unsafe { tree.try_remove(ptr(&elems[0])) }, Err(TreeError::CorruptLinks));
+    elems[0].node.set_left(core::ptr::null_mut());
+    assert_eq!(unsafe { tree.try_next(ptr(&elems[0])) }.map(|n| n.map(key)), Ok(Some(1)));
+
+    // Searches past the end go round a cycle too
+    elems[2].node.set_right(tree.root);
+    assert!(matches!(tree.try_entry(&3), Err(TreeError::CorruptLinks)));
+    assert!(matches!(tree.try_split_off(&3), Err(TreeError::CorruptLinks)));
+    elems[2].node.set_right(core::ptr::null_mut());
+    assert!(matches!(tree.try_entry(&3), Ok(Entry::Vacant(_))));
+    let right = tree.try_split_off(&1).unwrap();
+    assert_eq!(tree.iter().map(|e| e.key).collect::<Vec<_>>(), [0]);
+    assert_eq!(right.iter().map(|e| e.key).collect::<Vec<_>>(), [1, 2]);
+  }
+
+  #[test]
+  fn cursor() {
+    let elems = make(&shuffled(100));
+    let mut tree = build(&elems);
+    let mut cursor = tree.cursor_first();
+    let mut seen = Vec::new();
+    while let Some(elem) = cursor.get() {
+      assert_eq!(cursor.peek_prev().map(|e| e.key), seen.last().cloned());
+      seen.push(elem.key);
+      cursor.move_next();
+    }
+    assert_eq!(seen, (0..100).collect::<Vec<_>>());
+    cursor.move_prev();
+    assert_eq!(cursor.get().map(|e| e.key), Some(99));
+    assert_eq!(cursor.peek_next().map(|e| e.key), None);
+
+    // Unlink the odd elements
+    let mut cursor = tree.cursor_lower_bound_mut(&1);
+    while cursor.get().is_some() {
+      let removed = cursor.remove_current().map(key);
+      assert_eq!(removed.map(|k| k % 2), Some(1));
+      if cursor.get().is_some() {
+        cursor.move_next();
+      }
+    }
+    assert_eq!(check(&tree), (0..50).map(|k| k * 2).collect::<Vec<_>>());
+
+    // And put some back
+    let odd = make(&[41, 43, 45, 1000]);
+    let mut cursor = unsafe { tree.cursor_at_mut(ptr(&elems[index_of(&elems, 42)])) }.unwrap();
+    assert_eq!(unsafe { cursor.insert_before(ptr(&odd[0])) }, Ok(()));
+    assert_eq!(unsafe { cursor.insert_after(ptr(&odd[1])) }, Ok(()));
+    assert_eq!(unsafe { cursor.insert_after(ptr(&odd[2])) }, Err(TreeError::OutOfOrder));
+    assert_eq!(cursor.get().map(|e| e.key), Some(42));
+    cursor.move_next();
+    assert_eq!(cursor.get().map(|e| e.key), Some(43));
+    cursor.move_next();
+    let old = unsafe { cursor.replace_current(ptr(&odd[2])) }.map(key);
+    assert_eq!(old, Ok(44));
+    while cursor.get().is_some() {
+      cursor.move_next();
+    }
+    assert_eq!(unsafe { cursor.insert_before(ptr(&odd[3])) }, Ok(()));
+    let keys = check(&tree);
+    assert_eq!(keys.len(), 53);
+    assert_eq!(&keys[20..24], &[40, 41, 42, 43]);
+    assert_eq!(keys[24], 45);
+    assert_eq!(keys.last(), Some(&1000));
+  }
+
+  fn index_of(elems: &[Elem], key: u32) -> usize {
+    elems.iter().position(|e| e.key == key).unwrap()
+  }
+
+  #[test]
+  fn iter() {
+    let elems = make(&shuffled(200));
+    let mut tree = build(&elems);
+    assert!(tree.iter().map(|e| e.key).eq(0..200));
+    assert!(tree.iter().rev().map(|e| e.key).eq((0..200).rev()));
+
+    // The ends meet in the middle, whichever way they're taken
+    for split in [0, 1, 99, 199, 200] {
+      let mut iter = tree.iter();
+      let front: Vec<u32> = iter.by_ref().take(split).map(|e| e.key).collect();
+      let back: Vec<u32> = iter.rev().map(|e| e.key).collect();
+      assert_eq!(front.len() + back.len(), 200);
+      assert_eq!(front.last().map(|k| k + 1).unwrap_or(0), back.last().cloned().unwrap_or(200));
+    }
+
+    for elem in &mut tree {
+      let elem = unsafe { elem.get_unchecked_mut() };
+      elem.rev = elem.key * 2;
+    }
+    assert_eq!((&tree).into_iter().nth(7).map(|e| e.rev), Some(14));
+    assert_eq!(Tree::<Elem, ByKey>::new().iter().next_back().map(|e| e.key), None);
+  }
+
+  #[test]
+  fn range() {
+    use core::ops::Bound::{Excluded, Unbounded};
+
+    let keys: Vec<u32> = shuffled(100).iter().map(|k| k / 2).collect();
+    let elems = make(&keys);
+    let mut tree = build(&elems);
+    assert_eq!(tree.range(10..13).map(|e| e.key).collect::<Vec<_>>(), [10, 10, 11, 11, 12, 12]);
+    assert_eq!(tree.range(..=1).rev().map(|e| e.key).collect::<Vec<_>>(), [1, 1, 0, 0]);
+    let above = tree.range::<u32, _>((Excluded(47), Unbounded)).map(|e| e.key);
+    assert_eq!(above.collect::<Vec<_>>(), [48, 48, 49, 49]);
+    assert_eq!(tree.range(60..).count(), 0);
+    assert_eq!(tree.range(20..20).count(), 0);
+    assert_eq!(tree.range(..).count(), 100);
+
+    let mut range = tree.range(30..=32);
+    assert_eq!(range.next_back().map(|e| e.key), Some(32));
+    assert_eq!(range.next().map(|e| e.key), Some(30));
+    assert_eq!(range.count(), 4);
+
+    for elem in tree.range_mut(5..7) {
+      unsafe { elem.get_unchecked_mut() }.rev = 0;
+    }
+    assert_eq!(tree.iter().filter(|e| e.rev == 0).count(), 4);
+  }
+
+  #[test]
+  #[should_panic(expected = "range start is greater than range end")]
+  fn range_backwards() {
+    use core::ops::Bound::Included;
+
+    let elems = make(&[1, 2, 3]);
+    let tree = build(&elems);
+    let _ = tree.range::<u32, _>((Included(3), Included(1)));
+  }
+
+  #[test]
+  fn drain() {
+    let elems = make(&shuffled(300));
+    let mut tree = build(&elems);
+    let mut seen = Vec::new();
+    tree.clear_with(|node| {
+      // Children come out before their parents, already unlinked
+      let links = &unsafe { node.as_ref() }.node;
+      assert!(links.left().is_null() && links.right().is_null());
+      seen.push(key(node));
+    });
+    assert!(tree.first().is_none());
+    seen.sort();
+    assert_eq!(seen, (0..300).collect::<Vec<_>>());
+
+    // Dropping a drain part way still unlinks the rest, ready for reuse
+    let mut tree = build(&elems);
+    assert_eq!(tree.drain().take(10).count(), 10);
+    assert!(tree.iter().next().is_none());
+    let tree = build(&elems);
+    assert_eq!(check(&tree).len(), 300);
+  }
+
+  #[test]
+  fn extract() {
+    let elems = make(&shuffled(200));
+    let mut tree = build(&elems);
+    let taken: Vec<u32> = tree.extract_if(50..150, |e| e.key % 3 == 0).map(key).collect();
+    assert_eq!(taken, (50..150).filter(|k| k % 3 == 0).collect::<Vec<_>>());
+    assert_eq!(check(&tree).len(), 200 - taken.len());
+
+    // Dropped part way, the rest stay
+    assert_eq!(tree.extract_if(.., |_| true).take(5).map(key).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+    assert_eq!(tree.first().map(key), Some(5));
+
+    tree.retain(|e| e.key % 2 == 1);
+    let keys = check(&tree);
+    assert!(keys.iter().all(|k| k % 2 == 1));
+    assert_eq!(keys.len(), 100 - (50..150).filter(|k| k % 6 == 3).count() - 2);
+  }
+
+  #[test]
+  fn extract_equal_keys() {
+    for step in 2..6 {
+      let mut elems = make(&shuffled(600).iter().map(|k| k % 7).collect::<Vec<_>>());
+      for (i, elem) in elems.iter_mut().enumerate() {
+        elem.rev = i as u32;
+      }
+      let mut tree = build(&elems);
+      let order: Vec<u32> = tree.iter().map(|e| e.rev).collect();
+      let taken: Vec<u32> = tree.extract_if(.., |e| e.rev % step != 0).map(|e| unsafe { e.as_ref() }.rev).collect();
+      assert_eq!(taken, order.iter().copied().filter(|r| r % step != 0).collect::<Vec<_>>());
+      assert_eq!(check(&tree).len(), order.len() - taken.len());
+      assert!(tree.iter().map(|e| e.rev).eq(order.iter().copied().filter(|r| r % step == 0)));
+    }
+  }
+
+  #[test]
+  fn from_sorted() {
+    for n in [0, 1, 2, 5, 6, 7, 8, 100, 1000] {
+      let elems = make(&(0..n).map(|k| k / 3).collect::<Vec<_>>());
+      let tree = unsafe { Tree::<Elem, ByKey>::from_sorted_iter(elems.iter().map(ptr)) };
+      assert_eq!(check(&tree), (0..n).map(|k| k / 3).collect::<Vec<_>>());
+      assert!(tree.iter().map(|e| e as *const Elem).eq(elems.iter().map(|e| e as *const Elem)));
+    }
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic(expected = "input is not sorted")]
+  fn from_unsorted() {
+    let elems = make(&[1, 3, 2]);
+    let _ = unsafe { Tree::<Elem, ByKey>::from_sorted_iter(elems.iter().map(ptr)) };
+  }
+
+  #[test]
+  fn split_join() {
+    let keys: Vec<u32> = shuffled(300).iter().map(|k| k / 2).collect();
+    let elems = make(&keys);
+    for at in [0, 1, 37, 75, 149, 150] {
+      let mut tree = build(&elems);
+      let right = tree.split_off(&at);
+      assert_eq!(check(&tree), (0..at * 2).map(|k| k / 2).collect::<Vec<_>>());
+      assert_eq!(check(&right), (at * 2..300).map(|k| k / 2).collect::<Vec<_>>());
+
+      let mut left = tree;
+      let mut right = right;
+      left.append(&mut right);
+      assert!(right.first().is_none());
+      assert_eq!(check(&left).len(), 300);
+    }
+
+    // Appending before, and interleaved
+    let mut low = build(&elems[..100]);
+    let mut high = low.split_off(&25);
+    high.append(&mut low);
+    let mut rest = build(&elems[100..]);
+    high.append(&mut rest);
+    let mut all = (0..300).map(|k| k / 2).collect::<Vec<_>>();
+    assert_eq!(check(&high), all);
+
+    let small = make(&[2000, 2001, 2002]);
+    let pivot = make(&[1000]);
+    let mut big = build(&elems);
+    let joined = unsafe { Tree::join(big.split_off(&0), ptr(&pivot[0]), build(&small)) };
+    all.extend([1000, 2000, 2001, 2002]);
+    assert_eq!(check(&joined), all);
+  }
+
+  #[test]
+  fn set_algebra() {
+    use alloc::collections::BTreeSet;
+
+    let ours: Vec<u32> = (0..200).filter(|k| k % 2 == 0).collect();
+    let theirs: Vec<u32> = (100..400).filter(|k| k % 3 == 0).collect();
+    let (ours_set, theirs_set): (BTreeSet<u32>, BTreeSet<u32>) =
+      (ours.iter().cloned().collect(), theirs.iter().cloned().collect());
+    let (a, b) = (make(&ours), make(&theirs));
+    let own = |ptr: NonNull<Elem>| a.iter().any(|e| core::ptr::eq(e, ptr.as_ptr()));
+
+    type Op = fn(&mut Tree<Elem, ByKey>, &mut Tree<Elem, ByKey>, &mut dyn FnMut(NonNull<Elem>));
+    let ops: [(Op, Vec<u32>); 4] = [
+      (|x, y, f| x.union(y, f), ours_set.union(&theirs_set).cloned().collect()),
+      (|x, y, f| x.intersection(y, f), ours_set.intersection(&theirs_set).cloned().collect()),
+      (|x, y, f| x.difference(y, f), ours_set.difference(&theirs_set).cloned().collect()),
+      (|x, y, f| x.symmetric_difference(y, f),
+       ours_set.symmetric_difference(&theirs_set).cloned().collect()),
+    ];
+    for (op, expected) in ops {
+      let (mut x, mut y) = (build(&a), build(&b));
+      let mut dropped = Vec::new();
+      op(&mut x, &mut y, &mut |node| dropped.push(node));
+      assert_eq!(check(&x), expected);
+      assert!(y.first().is_none());
+      assert_eq!(x.iter().count() + dropped.len(), a.len() + b.len());
+      // Where both had a key, ours is the one kept
+      assert!(x.iter().filter(|e| theirs_set.contains(&e.key) && ours_set.contains(&e.key))
+               .all(|e| own(ptr(e))));
+      assert!(dropped.iter().all(|&node| unsafe { node.as_ref() }.node.left().is_null()));
+    }
+  }
+
+  #[test]
+  fn navigate() {
+    let elems = make(&[40, 10, 30, 20]);
+    let tree = build(&elems);
+    assert_eq!(tree.first().map(key), Some(10));
+    assert_eq!(tree.last().map(key), Some(40));
+    let second = unsafe { tree.next(tree.first().unwrap()) };
+    assert_eq!(second.map(key), Some(20));
+    assert_eq!(unsafe { tree.prev(second.unwrap()) }.map(key), Some(10));
+    assert_eq!(tree.search(&25), None);
+    assert_eq!(tree.nsearch(&25).map(key), Some(30));
+    assert_eq!(tree.psearch(&25).map(key), Some(20));
+
+    let mut seen = Vec::new();
+    tree.walk(Some(&25), &mut |_, node| { seen.push(key(node)); None::<()> });
+    assert_eq!(seen, [30, 40]);
+    seen.clear();
+    tree.reverse_walk(Some(&25), &mut |_, node| { seen.push(key(node)); None::<()> });
+    assert_eq!(seen, [20, 10]);
+    let found = tree.walk(None, &mut |_, node| Some(key(node)).filter(|&k| k > 15));
+    assert_eq!(found, Some(20));
+    assert_eq!(tree.reverse_walk(Some(&5), &mut |_, node| Some(key(node))), None);
+  }
+
+  /// An element whose nodes of either kind keep their subtrees' sizes.
+  pub(super) struct Counted {
+    pub(super) key: u32,
+    node:           Node<Counted, Size>,
+    parent:         ParentNode<Counted, Size>,
+  }
+
+  impl Intrusive<Node<Counted, Size>> for Counted {
+    fn field(&self) -> &Node<Counted, Size> {
+      &self.node
+    }
+  }
+
+  impl Intrusive<ParentNode<Counted, Size>> for Counted {
+    fn field(&self) -> &ParentNode<Counted, Size> {
+      &self.parent
+    }
+  }
+
+  pub(super) struct CountedKey;
+
+  impl KeyAdapter<Counted> for CountedKey {
+    type Key = u32;
+
+    fn key(node: &Counted) -> &u32 {
+      &node.key
+    }
+  }
+
+  pub(super) fn counted(keys: &[u32]) -> Vec<Counted> {
+    keys.iter().map(|&key| Counted { key, node: Node::new(), parent: ParentNode::new() }).collect()
+  }
+
+  /// Check every subtree's size, returning the tree's.
+  pub(super) fn check_sizes<L>(node: *mut Counted) -> usize
+    where Counted: Intrusive<L>, L: Links<Counted, Summary = Size>
+  {
+    if node.is_null() {
+      return 0
+    }
+    let links: &L = node.field();
+    let n = check_sizes::<L>(links.left()) + 1 + check_sizes::<L>(links.right());
+    assert_eq!(links.summary(), Size(n), "stale subtree size");
+    n
+  }
+
+  #[test]
+  fn order_statistics() {
+    let keys: Vec<u32> = shuffled(300).iter().map(|k| k / 2).collect();
+    let elems = counted(&keys);
+    let sizes = check_sizes::<Node<Counted, Size>>;
+    let mut tree: Tree<Counted, CountedKey, Natural, Size> = Tree::new();
+    assert!(tree.is_empty());
+    for elem in &elems {
+      unsafe { tree.insert(NonNull::from(elem)) };
+    }
+    assert_eq!(sizes(tree.root), 300);
+    assert_eq!(tree.len(), 300);
+    for k in 0..300 {
+      assert_eq!(tree.select(k).map(|node| unsafe { node.as_ref() }.key), Some(k as u32 / 2));
+    }
+    assert_eq!(tree.select(300), None);
+    assert_eq!(tree.rank(&0), 0);
+    assert_eq!(tree.rank(&70), 140);
+    assert_eq!(tree.rank(&1000), 300);
+
+    for elem in elems.iter().step_by(3) {
+      unsafe { tree.remove(NonNull::from(elem)) };
+      sizes(tree.root);
+    }
+    assert_eq!(tree.len(), 200);
+
+    let mut right = tree.split_off(&75);
+    assert_eq!(sizes(tree.root) + sizes(right.root), 200);
+    assert_eq!(tree.len(), tree.iter().count());
+    tree.append(&mut right);
+    assert_eq!(sizes(tree.root), 200);
+    assert_eq!(tree.rank(&150), 200);
+  }
+
+  /// A free extent, keyed by address, summarized by the largest length.
+  struct Extent {
+    addr: u32,
+    len:  u32,
+    node: Node<Extent, Largest>,
+  }
+
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  struct Largest(u32);
+
+  impl Augment<Extent> for Largest {
+    const EMPTY: Largest = Largest(0);
+
+    fn combine(left: Largest, node: &Extent, right: Largest) -> Largest {
+      Largest(left.0.max(node.len).max(right.0))
+    }
+  }
+
+  impl Intrusive<Node<Extent, Largest>> for Extent {
+    fn field(&self) -> &Node<Extent, Largest> {
+      &self.node
+    }
+  }
+
+  struct ByAddr;
+
+  impl KeyAdapter<Extent> for ByAddr {
+    type Key = u32;
+
+    fn key(node: &Extent) -> &u32 {
+      &node.addr
+    }
+  }
+
+  fn extents(n: u32) -> Vec<Extent> {
+    shuffled(n).iter().map(|&addr| Extent { addr, len: addr * 7 % 31, node: Node::new() }).collect()
+  }
+
+  #[test]
+  fn fold_range() {
+    use core::ops::Bound::{Excluded, Included};
+
+    let elems = extents(200);
+    let mut tree: Tree<Extent, ByAddr, Natural, Largest> = Tree::new();
+    for elem in &elems {
+      unsafe { tree.insert(NonNull::from(elem)) };
+    }
+    let largest = |tree: &Tree<Extent, ByAddr, Natural, Largest>, lo: u32, hi: u32| {
+      Largest(tree.range(lo..hi).map(|e| e.len).max().unwrap_or(0))
+    };
+    for (lo, hi) in [(0, 200), (0, 0), (5, 6), (3, 17), (40, 41), (100, 190), (150, 400)] {
+      assert_eq!(tree.fold_range(lo..hi), largest(&tree, lo, hi));
+    }
+    assert_eq!(tree.fold_range(..), Largest(30));
+    assert_eq!(tree.fold_range((Excluded(9), Included(11))), largest(&tree, 10, 12));
+
+    // Taking out every extent of the largest length
+    for elem in elems.iter().filter(|e| e.len >= 25) {
+      unsafe { tree.remove(NonNull::from(elem)) };
+    }
+    assert_eq!(tree.fold_range(..), Largest(24));
+    for lo in (0..200).step_by(13) {
+      assert_eq!(tree.fold_range(lo..lo + 20), largest(&tree, lo, lo + 20));
+    }
+  }
+
+  struct Mapping {
+    span: core::ops::Range<u32>,
+    node: Node<Mapping, MaxEnd<Mapping, BySpan>>,
+  }
+
+  struct BySpan;
+
+  impl IntervalAdapter<Mapping> for BySpan {
+    type Point = u32;
+
+    fn start(node: &Mapping) -> &u32 {
+      &node.span.start
+    }
+
+    fn end(node: &Mapping) -> &u32 {
+      &node.span.end
+    }
+  }
+
+  impl Intrusive<Node<Mapping, MaxEnd<Mapping, BySpan>>> for Mapping {
+    fn field(&self) -> &Node<Mapping, MaxEnd<Mapping, BySpan>> {
+      &self.node
+    }
+  }
+
+  #[test]
+  fn intervals() {
+    let elems: Vec<Mapping> = shuffled(300).iter()
+      .map(|&k| Mapping { span: k * 3..k * 3 + 1 + k * 11 % 17, node: Node::new() })
+      .collect();
+    let mut tree: IntervalTree<Mapping, BySpan> = Tree::new();
+    for elem in &elems {
+      unsafe { tree.insert(NonNull::from(elem)) };
+    }
+    let spans = |iter: &mut dyn Iterator<Item = &Mapping>| iter.map(|e| e.span.clone()).collect::<Vec<_>>();
+    let overlapping = |tree: &IntervalTree<Mapping, BySpan>, range: core::ops::Range<u32>| {
+      spans(&mut tree.iter().filter(|e| e.span.start < range.end && range.start < e.span.end))
+    };
+    for range in [0..1, 0..900, 10..11, 100..140, 451..452, 895..2000, 2000..3000] {
+      assert_eq!(spans(&mut tree.overlapping(range.clone())), overlapping(&tree, range.clone()));
+      assert_eq!(tree.any_overlap(range.clone()).is_some(), !overlapping(&tree, range).is_empty());
+    }
+    for point in [0, 7, 300, 301, 899, 5000] {
+      assert_eq!(spans(&mut tree.stab(point)), overlapping(&tree, point..point + 1));
+    }
+    // Empty ranges overlap nothing, even inside an interval
+    for point in [0, 5, 301, 899] {
+      assert!(tree.stab(point).next().is_some());
+      assert_eq!(tree.overlapping(point..point).count(), 0);
+      assert!(tree.any_overlap(point..point).is_none());
+    }
+
+    for elem in elems.iter().filter(|e| e.span.len() > 8) {
+      unsafe { tree.remove(NonNull::from(elem)) };
+    }
+    assert_eq!(tree.root.field().summary().end(), tree.iter().map(|e| e.span.end).max());
+    for start in (0..900).step_by(41) {
+      let range = start..start + 25;
+      assert_eq!(spans(&mut tree.overlapping(range.clone())), overlapping(&tree, range.clone()));
+      assert_eq!(tree.any_overlap(range.clone()).is_some(), !overlapping(&tree, range).is_empty());
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "range start is greater than range end")]
+  fn intervals_backwards() {
+    let tree: IntervalTree<Mapping, BySpan> = Tree::new();
+    let _ = tree.overlapping(core::ops::Range { start: 5, end: 2 });
+  }
+
+  #[test]
+  fn find_first_where() {
+    let elems = extents(200);
+    let mut tree: Tree<Extent, ByAddr, Natural, Largest> = Tree::new();
+    for elem in &elems {
+      unsafe { tree.insert(NonNull::from(elem)) };
+    }
+    let first_fit = |tree: &Tree<Extent, ByAddr, Natural, Largest>, len: u32| {
+      tree.iter().find(|e| e.len >= len).map(|e| e.addr)
+    };
+    for len in [0, 1, 10, 29, 30, 31] {
+      assert_eq!(tree.find_first_where(|s| s.0 >= len).map(|e| e.addr), first_fit(&tree, len));
+    }
+    for elem in elems.iter().filter(|e| e.addr < 120) {
+      unsafe { tree.remove(NonNull::from(elem)) };
+    }
+    for len in [5, 25, 30] {
+      assert_eq!(tree.find_first_where(|s| s.0 >= len).map(|e| e.addr), first_fit(&tree, len));
+    }
+  }
+
+  #[test]
+  fn length() {
+    let counting = |elems: &[Elem]| {
+      let mut tree = Tree::<Elem, ByKey, Natural, (), usize>::new();
+      for elem in elems {
+        unsafe { tree.insert(ptr(elem)) };
+      }
+      tree
+    };
+    let elems = make(&shuffled(300));
+    let mut tree = counting(&elems);
+    assert_eq!(tree.len(), 300);
+    assert_eq!(tree.iter().len(), 300);
+    let mut iter = tree.iter_mut();
+    iter.next();
+    iter.next_back();
+    assert_eq!(iter.size_hint(), (298, Some(298)));
+    // Trees that don't count make no promises, unless their nodes count
+    let uncounted = make(&[1, 2]);
+    assert_eq!(build(&uncounted).iter().size_hint(), (0, None));
+    let sized = counted(&[1, 2, 3]);
+    let mut sized_tree = Tree::<Counted, CountedKey, Natural, Size>::new();
+    for elem in &sized {
+      unsafe { sized_tree.insert(NonNull::from(elem)) };
+    }
+    assert_eq!((sized_tree.len(), sized_tree.iter().len()), (3, 3));
+
+    for elem in &elems[..100] {
+      unsafe { tree.remove(ptr(elem)) };
+    }
+    assert_eq!(tree.len(), 200);
+    match tree.entry(&elems[100].key) {
+      Entry::Occupied(entry) => entry.remove(),
+      Entry::Vacant(_)       => panic!("{} is present", elems[100].key),
+    };
+    let mut cursor = tree.cursor_first_mut();
+    cursor.remove_current();
+    assert_eq!(tree.len(), 198);
+    let old = tree.first().unwrap();
+    let replacement = make(&[key(old)]);
+    assert_eq!(unsafe { tree.insert_or_replace(ptr(&replacement[0])) }, Some(old));
+    assert_eq!(tree.len(), 198);
+
+    for at in [0, 1, 150, 250, 300] {
+      let mut high = tree.split_off(&at);
+      assert_eq!((tree.len(), high.len()), (tree.iter().count(), high.iter().count()));
+      tree.append(&mut high);
+      assert_eq!((tree.len(), high.len()), (198, 0));
+    }
+    let mut high = tree.split_off(&250);
+    let mut low = tree.split_off(&10);
+    low.append(&mut high);
+    low.append(&mut tree);
+    assert!(tree.is_empty() && high.is_empty());
+    assert_eq!(low.len(), 198);
+    let high = low.split_off(&150);
+    let pivot = low.last().unwrap();
+    unsafe { low.remove(pivot) };
+    let mut low = unsafe { Tree::join(low, pivot, high) };
+    assert_eq!(low.len(), 198);
+
+    let mut other = counting(&elems[..100]);
+    low.union(&mut other, |_| ());
+    assert_eq!((low.len(), other.len()), (298, 0));
+    low.retain(|e| e.key % 3 != 0);
+    let kept = low.len();
+    assert_eq!(kept, low.iter().count());
+    assert_eq!(low.extract_if(.., |e| e.key % 2 == 0).count() + low.len(), kept);
+    let mut other = counting(&elems[..0]);
+    low.symmetric_difference(&mut other, |_| ());
+    assert_eq!(low.len(), low.iter().count());
+    low.intersection(&mut other, |_| ());
+    assert_eq!(low.len(), 0);
+
+    let sorted = make(&(0..50).collect::<Vec<_>>());
+    let mut tree = unsafe { Tree::<Elem, ByKey, Natural, (), usize>::from_sorted_iter(sorted.iter().map(ptr)) };
+    assert_eq!(tree.len(), 50);
+    assert_eq!(tree.drain().count(), 50);
+    assert_eq!(tree.len(), 0);
+  }
+
+
 }
@@ -0,0 +1,115 @@
+use core::cmp::Ordering::Greater;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use super::{update, Augment, Compare, Counter, KeyAdapter, Node, Tree};
+
+
+/// The most keys a 2-3 tree `height` levels deep can hold.
+#[inline]
+fn max_keys(height: u32) -> usize {
+  3usize.saturating_pow(height) - 1
+}
+
+/// Take the head off a list threaded through right links.
+#[inline]
+unsafe fn pop<T, S>(list: &mut *mut T) -> *mut T where T: Intrusive<Node<T, S>>, S: Augment<T> {
+  let node = *list;
+  *list = node.field().right();
+  node
+}
+
+/// Link the first `n` elements of the list threaded through `list`'s right
+/// links into a tree of black height `height`, returning its root.
+///
+/// This is the 2-3 tree the left-leaning red-black tree stands for: each
+/// level is split as evenly as it can be among 2-nodes, or 3-nodes if the
+/// elements won't fit otherwise. A 3-node is a black node with a red left
+/// child. Recursion only goes `height` deep, so no more than `usize::BITS`.
+unsafe fn link_sorted<T, S>(list: &mut *mut T, n: usize, height: u32) -> *mut T
+  where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  if height == 0 {
+    return ptr::null_mut()
+  }
+  let half = (n - 1) / 2;
+  if n - 1 - half <= max_keys(height - 1) {
+    let left = link_sorted(list, half, height - 1);
+    let node = pop(list);
+    let right = link_sorted(list, n - 1 - half, height - 1);
+    node.field().reset(false);
+    node.field().set_left(left);
+    node.field().set_right(right);
+    update(node);
+    node
+  } else {
+    let a = (n - 2) / 3;
+    let b = (n - 2 - a) / 2;
+    let left_left = link_sorted(list, a, height - 1);
+    let left = pop(list);
+    let left_right = link_sorted(list, b, height - 1);
+    let node = pop(list);
+    let right = link_sorted(list, n - 2 - a - b, height - 1);
+    left.field().reset(true);
+    left.field().set_left(left_left);
+    left.field().set_right(left_right);
+    update(left);
+    node.field().reset(false);
+    node.field().set_left(left);
+    node.field().set_right(right);
+    update(node);
+    node
+  }
+}
+
+impl<T, A, C, S, L> Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// A tree of the elements `iter` yields, which must be in order, linked
+  /// together directly in O(n) time rather than inserted one by one.
+  ///
+  /// The order is only checked in debug builds.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`, for every element.
+  pub unsafe fn from_sorted_iter<I>(iter: I) -> Tree<T, A, C, S, L>
+    where C: Default, I: IntoIterator<Item = NonNull<T>>
+  {
+    Tree::from_sorted_iter_with(C::default(), iter)
+  }
+
+  /// As `from_sorted_iter`, for a tree ordered by `cmp`.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`, for every element.
+  pub unsafe fn from_sorted_iter_with<I>(cmp: C, iter: I) -> Tree<T, A, C, S, L>
+    where I: IntoIterator<Item = NonNull<T>>
+  {
+    let mut tree: Tree<T, A, C, S, L> = Tree::with_comparator(cmp);
+    // Count the elements, threading them into a list through their right
+    // links on the way.
+    let mut head: *mut T = ptr::null_mut();
+    let mut tail: *mut T = ptr::null_mut();
+    let mut n: usize = 0;
+    for node in iter {
+      let node = node.as_ptr();
+      if tail.is_null() {
+        head = node;
+      } else {
+        debug_assert!(tree.order(&*tail, &*node) != Greater, "from_sorted_iter: input is not sorted");
+        tail.field().set_right(node);
+      }
+      tail = node;
+      n += 1;
+    }
+    // The tallest 2-3 tree they can fill: 2^height - 1 <= n, so there are
+    // enough for 2-nodes all the way down, and n < 2^(height + 1) - 1 is at
+    // most 3^height - 1, so 3-nodes can take the rest.
+    let height = usize::BITS - (n + 1).leading_zeros() - 1;
+    tree.root = link_sorted(&mut head, n, height);
+    tree.len.add(n);
+    tree
+  }
+}
@@ -0,0 +1,475 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering::{Greater, Less};
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ops::RangeBounds;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use super::{intact, leftmost, path_next, path_prev, rightmost, Augment, Compare, Counter, KeyAdapter, Natural, Node,
+            PathElem, Tree, TreeError, PATH_LEN};
+
+
+/// Where a cursor is: the path from the root down to an element, or nowhere,
+/// between the last element and the first.
+struct Position<T, S> {
+  path:     [PathElem<T>; PATH_LEN],
+  depth:    Option<usize>,
+  _summary: PhantomData<S>,
+}
+
+impl<T, S> Position<T, S> where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  #[inline]
+  fn new() -> Position<T, S> {
+    Position {
+      path:     [PathElem::new(ptr::null_mut()); PATH_LEN],
+      depth:    None,
+      _summary: PhantomData,
+    }
+  }
+
+  #[inline]
+  fn current(&self) -> *mut T {
+    match self.depth {
+      Some(i) => self.path[i].node,
+      None    => ptr::null_mut(),
+    }
+  }
+
+  #[inline]
+  fn move_next<A, C, L>(&mut self, tree: &Tree<T, A, C, S, L>)
+    where A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+  {
+    self.depth = match self.depth {
+      Some(i) => intact(path_next(&mut self.path, i)),
+      None    => intact(tree.first_path(&mut self.path)),
+    }
+  }
+
+  #[inline]
+  fn move_prev<A, C, L>(&mut self, tree: &Tree<T, A, C, S, L>)
+    where A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+  {
+    self.depth = match self.depth {
+      Some(i) => intact(path_prev(&mut self.path, i)),
+      None    => intact(tree.last_path(&mut self.path)),
+    }
+  }
+
+  /// The element `move_next` would go to, leaving the path as it is.
+  #[inline]
+  fn peek_next<A, C, L>(&self, tree: &Tree<T, A, C, S, L>) -> *mut T
+    where A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+  {
+    let i = match self.depth {
+      Some(i) => i,
+      None    => return leftmost(tree.root),
+    };
+    let right = self.path[i].node.field().right();
+    if !right.is_null() {
+      return leftmost(right)
+    }
+    self.path[..i].iter().rev().find(|elem| elem.cmp == Less).map_or(ptr::null_mut(), |elem| elem.node)
+  }
+
+  /// The element `move_prev` would go to, leaving the path as it is.
+  #[inline]
+  fn peek_prev<A, C, L>(&self, tree: &Tree<T, A, C, S, L>) -> *mut T
+    where A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+  {
+    let i = match self.depth {
+      Some(i) => i,
+      None    => return rightmost(tree.root),
+    };
+    let left = self.path[i].node.field().left();
+    if !left.is_null() {
+      return rightmost(left)
+    }
+    self.path[..i].iter().rev().find(|elem| elem.cmp == Greater).map_or(ptr::null_mut(), |elem| elem.node)
+  }
+}
+
+/// A read-only position in a tree. Stepping is amortized O(1), as the cursor
+/// keeps the path from the root to its element rather than searching again.
+///
+/// Besides the elements, a cursor may be at a "ghost" position between the
+/// last element and the first.
+pub struct Cursor<'a, T, A, C = Natural, S = (), L = ()> {
+  tree: &'a Tree<T, A, C, S, L>,
+  pos:  Position<T, S>,
+}
+
+/// A position in a tree at which elements can also be linked and unlinked.
+pub struct CursorMut<'a, T, A, C = Natural, S = (), L = ()> {
+  tree: &'a mut Tree<T, A, C, S, L>,
+  pos:  Position<T, S>,
+}
+
+/// Unlinks and hands out, in order, the elements within a range that a
+/// predicate picks. Those after the last taken are left alone.
+pub struct ExtractIf<'a, T, A, C, S, L, Q: ?Sized, R, F> {
+  cursor: CursorMut<'a, T, A, C, S, L>,
+  range:  R,
+  pred:   F,
+  _key:   PhantomData<fn(&Q)>,
+}
+
+impl<T, A, C, S, L> Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  #[inline]
+  fn first_position(&self) -> Position<T, S> {
+    let mut pos = Position::new();
+    pos.depth = intact(self.first_path(&mut pos.path));
+    pos
+  }
+
+  #[inline]
+  fn last_position(&self) -> Position<T, S> {
+    let mut pos = Position::new();
+    pos.depth = intact(self.last_path(&mut pos.path));
+    pos
+  }
+
+  #[inline]
+  fn lower_bound_position<Q>(&self, key: &Q) -> Position<T, S>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    let mut pos = Position::new();
+    let f = |elem: &T| self.cmp.compare(A::key(elem).borrow(), key);
+    pos.depth = intact(self.nsearch_path(f, &mut pos.path));
+    pos
+  }
+
+  /// The position of the first element past the start of `range`.
+  #[inline]
+  fn range_start_position<Q, R>(&self, range: &R) -> Position<T, S>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    let mut pos = Position::new();
+    let f = |elem: &T| if self.above_start(elem, range) { Greater } else { Less };
+    pos.depth = intact(self.nsearch_path(f, &mut pos.path));
+    pos
+  }
+
+  #[inline]
+  unsafe fn position_at(&self, node: NonNull<T>) -> Result<Position<T, S>, TreeError> {
+    let mut pos = Position::new();
+    pos.depth = Some(self.path_to(node.as_ptr(), &mut pos.path)?);
+    Ok(pos)
+  }
+
+  /// A cursor at the first element.
+  #[inline]
+  pub fn cursor_first(&self) -> Cursor<'_, T, A, C, S, L> {
+    Cursor { pos: self.first_position(), tree: self }
+  }
+
+  /// A cursor at the last element.
+  #[inline]
+  pub fn cursor_last(&self) -> Cursor<'_, T, A, C, S, L> {
+    Cursor { pos: self.last_position(), tree: self }
+  }
+
+  /// A cursor at the first element not less than `key`.
+  #[inline]
+  pub fn cursor_lower_bound<Q>(&self, key: &Q) -> Cursor<'_, T, A, C, S, L>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    Cursor { pos: self.lower_bound_position(key), tree: self }
+  }
+
+  /// A cursor at `node`.
+  ///
+  /// # Safety
+  ///
+  /// `node` must point to a live element, though it need not be linked into
+  /// this tree.
+  #[inline]
+  pub unsafe fn cursor_at(&self, node: NonNull<T>) -> Result<Cursor<'_, T, A, C, S, L>, TreeError> {
+    Ok(Cursor { pos: self.position_at(node)?, tree: self })
+  }
+
+  /// A mutable cursor at the first element.
+  #[inline]
+  pub fn cursor_first_mut(&mut self) -> CursorMut<'_, T, A, C, S, L> {
+    CursorMut { pos: self.first_position(), tree: self }
+  }
+
+  /// A mutable cursor at the last element.
+  #[inline]
+  pub fn cursor_last_mut(&mut self) -> CursorMut<'_, T, A, C, S, L> {
+    CursorMut { pos: self.last_position(), tree: self }
+  }
+
+  /// A mutable cursor at the first element not less than `key`.
+  #[inline]
+  pub fn cursor_lower_bound_mut<Q>(&mut self, key: &Q) -> CursorMut<'_, T, A, C, S, L>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    CursorMut { pos: self.lower_bound_position(key), tree: self }
+  }
+
+  /// A mutable cursor at `node`.
+  ///
+  /// # Safety
+  ///
+  /// As for `cursor_at`.
+  #[inline]
+  pub unsafe fn cursor_at_mut(&mut self, node: NonNull<T>) -> Result<CursorMut<'_, T, A, C, S, L>, TreeError> {
+    Ok(CursorMut { pos: self.position_at(node)?, tree: self })
+  }
+
+  /// Unlink the elements within `range` for which `pred` holds, handing each
+  /// out as it goes.
+  ///
+  /// # Panics
+  ///
+  /// As for `range`.
+  pub fn extract_if<Q, R, F>(&mut self, range: R, pred: F) -> ExtractIf<'_, T, A, C, S, L, Q, R, F>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>, F: FnMut(&T) -> bool
+  {
+    self.check_range(&range);
+    ExtractIf {
+      cursor: CursorMut { pos: self.range_start_position(&range), tree: self },
+      range,
+      pred,
+      _key:   PhantomData,
+    }
+  }
+
+  /// Unlink every element for which `f` does not hold.
+  pub fn retain<F>(&mut self, mut f: F) where F: FnMut(&T) -> bool {
+    self.extract_if::<A::Key, _, _>(.., |elem| !f(elem)).for_each(drop)
+  }
+}
+
+impl<'a, T, A, C, S, L> Cursor<'a, T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// The element at the cursor, or `None` at the ghost position.
+  #[inline]
+  pub fn get(&self) -> Option<&'a T> {
+    unsafe { self.pos.current().as_ref() }
+  }
+
+  #[inline]
+  pub fn as_ptr(&self) -> Option<NonNull<T>> {
+    NonNull::new(self.pos.current())
+  }
+
+  #[inline]
+  pub fn move_next(&mut self) {
+    self.pos.move_next(self.tree)
+  }
+
+  #[inline]
+  pub fn move_prev(&mut self) {
+    self.pos.move_prev(self.tree)
+  }
+
+  #[inline]
+  pub fn peek_next(&self) -> Option<&'a T> {
+    unsafe { self.pos.peek_next(self.tree).as_ref() }
+  }
+
+  #[inline]
+  pub fn peek_prev(&self) -> Option<&'a T> {
+    unsafe { self.pos.peek_prev(self.tree).as_ref() }
+  }
+}
+
+impl<'a, T, A, C, S, L> CursorMut<'a, T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// The element at the cursor, or `None` at the ghost position.
+  #[inline]
+  pub fn get(&self) -> Option<&T> {
+    unsafe { self.pos.current().as_ref() }
+  }
+
+  #[inline]
+  pub fn as_ptr(&self) -> Option<NonNull<T>> {
+    NonNull::new(self.pos.current())
+  }
+
+  /// A read-only cursor at the same position.
+  #[inline]
+  pub fn as_cursor(&self) -> Cursor<'_, T, A, C, S, L> {
+    Cursor {
+      tree: self.tree,
+      pos:  Position { path: self.pos.path, depth: self.pos.depth, _summary: PhantomData },
+    }
+  }
+
+  #[inline]
+  pub fn move_next(&mut self) {
+    self.pos.move_next(self.tree)
+  }
+
+  #[inline]
+  pub fn move_prev(&mut self) {
+    self.pos.move_prev(self.tree)
+  }
+
+  #[inline]
+  pub fn peek_next(&self) -> Option<&T> {
+    unsafe { self.pos.peek_next(self.tree).as_ref() }
+  }
+
+  #[inline]
+  pub fn peek_prev(&self) -> Option<&T> {
+    unsafe { self.pos.peek_prev(self.tree).as_ref() }
+  }
+
+  /// Whether `node` may sit between `prev` and `next`, either of which may be
+  /// null for the ends of the tree.
+  #[inline]
+  unsafe fn fits(&self, prev: *mut T, node: *mut T, next: *mut T) -> bool {
+    (prev.is_null() || self.tree.order(&*prev, &*node) != Greater)
+      && (next.is_null() || self.tree.order(&*node, &*next) != Greater)
+  }
+
+  /// Find the path to `node` again after the tree was restructured.
+  #[inline]
+  fn reposition(&mut self, node: *mut T) {
+    self.pos.depth = if node.is_null() {
+      None
+    } else {
+      Some(intact(self.tree.path_to(node, &mut self.pos.path)))
+    };
+  }
+
+  /// Unlink the element at the cursor, moving to the one after it, in
+  /// O(log n) time. Returns `None` at the ghost position.
+  pub fn remove_current(&mut self) -> Option<NonNull<T>> {
+    let i = self.pos.depth?;
+    let node = self.pos.path[i].node;
+    self.pos.depth = intact(unsafe { self.tree.unlink_before(&mut self.pos.path, i) });
+    NonNull::new(node)
+  }
+
+  /// Link `node` in just before the cursor, or as the last element at the
+  /// ghost position. The cursor stays where it is.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not already be linked into a tree, and must stay put and
+  /// alive until it is removed.
+  pub unsafe fn insert_before(&mut self, node: NonNull<T>) -> Result<(), TreeError> {
+    let node = node.as_ptr();
+    let cur = self.pos.current();
+    if !self.fits(self.pos.peek_prev(self.tree), node, cur) {
+      return Err(TreeError::OutOfOrder)
+    }
+    let path = &mut self.pos.path;
+    let leaf = match self.pos.depth {
+      None    => self.tree.last_path(path)?.map_or(0, |i| {
+        path[i].cmp = Greater;
+        i + 1
+      }),
+      Some(i) => {
+        // Rightmost leaf of the left subtree
+        path[i].cmp = Less;
+        let (mut i, mut next) = (i, cur.field().left());
+        loop {
+          ensure!(i + 1 < path.len());
+          i += 1;
+          path[i].node = next;
+          if next.is_null() {
+            break i
+          }
+          path[i].cmp = Greater;
+          next = next.field().right();
+        }
+      },
+    };
+    ensure!(leaf < path.len());
+    self.tree.link(path, leaf, node);
+    self.reposition(cur);
+    Ok(())
+  }
+
+  /// Link `node` in just after the cursor, or as the first element at the
+  /// ghost position. The cursor stays where it is.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert_before`.
+  pub unsafe fn insert_after(&mut self, node: NonNull<T>) -> Result<(), TreeError> {
+    let node = node.as_ptr();
+    let cur = self.pos.current();
+    if !self.fits(cur, node, self.pos.peek_next(self.tree)) {
+      return Err(TreeError::OutOfOrder)
+    }
+    let path = &mut self.pos.path;
+    let leaf = match self.pos.depth {
+      None    => self.tree.first_path(path)?.map_or(0, |i| {
+        path[i].cmp = Less;
+        i + 1
+      }),
+      Some(i) => {
+        // Leftmost leaf of the right subtree
+        path[i].cmp = Greater;
+        let (mut i, mut next) = (i, cur.field().right());
+        loop {
+          ensure!(i + 1 < path.len());
+          i += 1;
+          path[i].node = next;
+          if next.is_null() {
+            break i
+          }
+          path[i].cmp = Less;
+          next = next.field().left();
+        }
+      },
+    };
+    ensure!(leaf < path.len());
+    self.tree.link(path, leaf, node);
+    self.reposition(cur);
+    Ok(())
+  }
+
+  /// Put `node` in place of the element at the cursor, returning the evicted
+  /// element. `NotLinked` is reported at the ghost position.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert_before`.
+  pub unsafe fn replace_current(&mut self, node: NonNull<T>) -> Result<NonNull<T>, TreeError> {
+    let node = node.as_ptr();
+    let i = self.pos.depth.ok_or(TreeError::NotLinked)?;
+    if !self.fits(self.pos.peek_prev(self.tree), node, self.pos.peek_next(self.tree)) {
+      return Err(TreeError::OutOfOrder)
+    }
+    let old = self.pos.path[i].node;
+    self.tree.replace_at(&self.pos.path, i, node);
+    self.pos.path[i].node = node;
+    Ok(NonNull::new_unchecked(old))
+  }
+}
+
+impl<'a, T, A, C, S, L, Q, R, F> Iterator for ExtractIf<'a, T, A, C, S, L, Q, R, F>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, A::Key: Borrow<Q>, C: Compare<A::Key> + Compare<Q>,
+        Q: ?Sized, R: RangeBounds<Q>, F: FnMut(&T) -> bool, L: Counter
+{
+  type Item = NonNull<T>;
+
+  fn next(&mut self) -> Option<NonNull<T>> {
+    loop {
+      let elem = self.cursor.get()?;
+      if !self.cursor.tree.below_end(elem, &self.range) {
+        return None
+      }
+      if (self.pred)(elem) {
+        return self.cursor.remove_current()
+      }
+      self.cursor.move_next();
+    }
+  }
+}
+
+impl<'a, T, A, C, S, L, Q, R, F> FusedIterator for ExtractIf<'a, T, A, C, S, L, Q, R, F>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, A::Key: Borrow<Q>, C: Compare<A::Key> + Compare<Q>,
+        Q: ?Sized, R: RangeBounds<Q>, F: FnMut(&T) -> bool, L: Counter { }
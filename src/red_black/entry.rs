@@ -0,0 +1,148 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering::{Equal, Greater, Less};
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use super::{Augment, Compare, Counter, KeyAdapter, Natural, Node, PathElem, Tree, TreeError, PATH_LEN};
+
+
+/// A position in a tree for some key, found by `Tree::entry`. It remembers
+/// the path down to that position so that it can be filled or emptied without
+/// searching again.
+pub enum Entry<'a, T, A, C = Natural, S = (), L = ()> {
+  Occupied(OccupiedEntry<'a, T, A, C, S, L>),
+  Vacant(VacantEntry<'a, T, A, C, S, L>),
+}
+
+/// A position holding an element.
+pub struct OccupiedEntry<'a, T, A, C = Natural, S = (), L = ()> {
+  tree:  &'a mut Tree<T, A, C, S, L>,
+  path:  [PathElem<T>; PATH_LEN],
+  depth: usize,
+}
+
+/// An empty position, where an element with the key would be linked.
+pub struct VacantEntry<'a, T, A, C = Natural, S = (), L = ()> {
+  tree:  &'a mut Tree<T, A, C, S, L>,
+  path:  [PathElem<T>; PATH_LEN],
+  depth: usize,
+}
+
+impl<T, A, C, S, L> Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// The position for `key`: an element equal to it, or where one would go.
+  #[inline]
+  pub fn entry<Q>(&mut self, key: &Q) -> Entry<'_, T, A, C, S, L>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    self.try_entry(key).unwrap_or_else(|err| panic!("{}", err))
+  }
+
+  /// As `entry`, or report corrupt links met on the way down.
+  pub fn try_entry<Q>(&mut self, key: &Q) -> Result<Entry<'_, T, A, C, S, L>, TreeError>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    path[0].node = self.root;
+    let mut i = 0;
+    while !path[i].node.is_null() {
+      let cur = path[i].node;
+      path[i].cmp = match self.cmp.compare(A::key(unsafe { &*cur }).borrow(), key) {
+        Greater => Less,
+        Less    => Greater,
+        Equal   => {
+          return Ok(Entry::Occupied(OccupiedEntry { tree: self, path, depth: i }))
+        },
+      };
+      ensure!(i + 1 < path.len());
+      path[i + 1].node = match path[i].cmp {
+        Less => cur.field().left(),
+        _    => cur.field().right(),
+      };
+      i += 1;
+    }
+    Ok(Entry::Vacant(VacantEntry { tree: self, path, depth: i }))
+  }
+}
+
+impl<'a, T, A, C, S, L> Entry<'a, T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// The element present, linking `node` first if there is none.
+  ///
+  /// # Safety
+  ///
+  /// As for `VacantEntry::insert`.
+  #[inline]
+  pub unsafe fn or_insert(self, node: NonNull<T>) -> &'a T {
+    match self {
+      Entry::Occupied(entry) => entry.into_ref(),
+      Entry::Vacant(entry)   => entry.insert(node),
+    }
+  }
+}
+
+impl<'a, T, A, C, S, L> OccupiedEntry<'a, T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  #[inline]
+  pub fn get(&self) -> &T {
+    unsafe { &*self.path[self.depth].node }
+  }
+
+  #[inline]
+  pub fn into_ref(self) -> &'a T {
+    unsafe { &*self.path[self.depth].node }
+  }
+
+  #[inline]
+  pub fn as_ptr(&self) -> NonNull<T> {
+    unsafe { NonNull::new_unchecked(self.path[self.depth].node) }
+  }
+
+  /// Put `node` in the element's exact position, returning the evicted
+  /// element.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not already be linked into a tree, must have a key equal to
+  /// the element's, and must stay put and alive until it is removed.
+  #[inline]
+  pub unsafe fn replace(self, node: NonNull<T>) -> NonNull<T> {
+    let old = self.as_ptr();
+    self.tree.replace_at(&self.path, self.depth, node.as_ptr());
+    old
+  }
+
+  /// Unlink the element, without comparing any keys.
+  #[inline]
+  pub fn remove(self) -> NonNull<T> {
+    self.try_remove().unwrap_or_else(|err| panic!("{}", err))
+  }
+
+  /// As `remove`, or report corrupt links met while rebalancing.
+  #[inline]
+  pub fn try_remove(mut self) -> Result<NonNull<T>, TreeError> {
+    let old = self.as_ptr();
+    unsafe { self.tree.unlink_at(&mut self.path, self.depth) }?;
+    Ok(old)
+  }
+}
+
+impl<'a, T, A, C, S, L> VacantEntry<'a, T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// Link `node` in at this position, rebalancing on the way back up the
+  /// remembered path.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not already be linked into a tree, must have a key equal to
+  /// the one looked up, and must stay put and alive until it is removed.
+  #[inline]
+  pub unsafe fn insert(mut self, node: NonNull<T>) -> &'a T {
+    self.tree.link(&mut self.path, self.depth, node.as_ptr());
+    &*node.as_ptr()
+  }
+}
@@ -0,0 +1,204 @@
+//! Interval trees: red-black trees of half-open intervals `[start, end)`,
+//! ordered by start, whose nodes keep the latest end in their subtrees.
+
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ops::Bound::{self, Excluded, Included, Unbounded};
+use core::ops::Range;
+use core::ptr;
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use super::iter::{check_range, Spine};
+use super::{summary, Augment, KeyAdapter, Natural, Node, Tree};
+
+
+/// How an interval tree finds the interval `[start, end)` of each element.
+///
+/// As with keys, an element's interval must not change while it is linked.
+pub trait IntervalAdapter<T> {
+  type Point: Ord + Copy;
+
+  fn start(node: &T) -> &Self::Point;
+  fn end(node: &T) -> &Self::Point;
+}
+
+/// Orders the elements of an interval tree by their starts.
+pub struct ByStart<A>(PhantomData<A>);
+
+impl<T, A> KeyAdapter<T> for ByStart<A> where A: IntervalAdapter<T> {
+  type Key = A::Point;
+
+  #[inline]
+  fn key(node: &T) -> &A::Point {
+    A::start(node)
+  }
+}
+
+/// The latest end of the intervals in a subtree, if it has any.
+pub struct MaxEnd<T, A> where A: IntervalAdapter<T> {
+  end:      Option<A::Point>,
+  _adapter: PhantomData<A>,
+}
+
+impl<T, A> MaxEnd<T, A> where A: IntervalAdapter<T> {
+  #[inline]
+  pub fn end(self) -> Option<A::Point> {
+    self.end
+  }
+}
+
+impl<T, A> Clone for MaxEnd<T, A> where A: IntervalAdapter<T> {
+  #[inline]
+  fn clone(&self) -> MaxEnd<T, A> {
+    *self
+  }
+}
+
+impl<T, A> Copy for MaxEnd<T, A> where A: IntervalAdapter<T> { }
+
+impl<T, A> Augment<T> for MaxEnd<T, A> where A: IntervalAdapter<T> {
+  const EMPTY: MaxEnd<T, A> = MaxEnd { end: None, _adapter: PhantomData };
+
+  #[inline]
+  fn combine(left: MaxEnd<T, A>, node: &T, right: MaxEnd<T, A>) -> MaxEnd<T, A> {
+    let end = Some(*A::end(node)).max(left.end).max(right.end);
+    MaxEnd { end, _adapter: PhantomData }
+  }
+}
+
+/// A tree of intervals, ordered by start. Elements must implement
+/// `Intrusive<Node<T, MaxEnd<T, A>>>`.
+pub type IntervalTree<T, A, L = ()> = Tree<T, ByStart<A>, Natural, MaxEnd<T, A>, L>;
+
+/// Whether anything in the subtree at `node` ends after `point`.
+#[inline]
+fn ends_after<T, A>(node: *mut T, point: &A::Point) -> bool
+  where T: Intrusive<Node<T, MaxEnd<T, A>>>, A: IntervalAdapter<T>
+{
+  summary::<T, Node<T, MaxEnd<T, A>>>(node).end.is_some_and(|end| end > *point)
+}
+
+impl<T, A, L> Tree<T, ByStart<A>, Natural, MaxEnd<T, A>, L>
+  where T: Intrusive<Node<T, MaxEnd<T, A>>>, A: IntervalAdapter<T>
+{
+  /// The elements whose intervals overlap `range`, in order of their starts.
+  /// An empty range overlaps nothing.
+  ///
+  /// Subtrees ending before `range` are skipped over, so finding k elements
+  /// takes O(min(n, (k + 1) log n)) time, and less the closer together they
+  /// are. This is not O(log n + k): knowing only the latest end under each
+  /// node, the search may have to go down a path of its own to each element
+  /// it finds.
+  ///
+  /// # Panics
+  ///
+  /// If `range` starts after it ends.
+  #[inline]
+  pub fn overlapping(&self, range: Range<A::Point>) -> Overlapping<'_, T, A> {
+    check_range(&Natural, &range);
+    let root = match range.is_empty() {
+      true  => ptr::null_mut(),
+      false => self.root,
+    };
+    Overlapping::new(root, range.start, Excluded(range.end))
+  }
+
+  /// The elements whose intervals contain `point`, in order of their starts.
+  /// The time taken is as for `overlapping`.
+  #[inline]
+  pub fn stab(&self, point: A::Point) -> Overlapping<'_, T, A> {
+    Overlapping::new(self.root, point, Included(point))
+  }
+
+  /// An element whose interval overlaps `range`, if there is one, in
+  /// O(log n) time. An empty range overlaps nothing.
+  ///
+  /// # Panics
+  ///
+  /// If `range` starts after it ends.
+  pub fn any_overlap(&self, range: Range<A::Point>) -> Option<&T> {
+    check_range(&Natural, &range);
+    if range.is_empty() {
+      return None
+    }
+    let mut node = self.root;
+    while let Some(elem) = unsafe { node.as_ref() } {
+      if *A::start(elem) < range.end && range.start < *A::end(elem) {
+        return Some(elem)
+      }
+      // If anything on the left ends after the start of the range and yet
+      // does not overlap it, it starts after the range, as does everything
+      // on the right.
+      let left = node.field().left();
+      node = match ends_after(left, &range.start) {
+        true  => left,
+        false => node.field().right(),
+      };
+    }
+    None
+  }
+}
+
+/// An iterator over the elements of an interval tree that end after one
+/// point, and start before another.
+pub struct Overlapping<'a, T, A>
+  where T: Intrusive<Node<T, MaxEnd<T, A>>>, A: IntervalAdapter<T>
+{
+  // As for a forward walk, but leaving out subtrees that end too soon.
+  stack:   Spine<T, Node<T, MaxEnd<T, A>>>,
+  after:   A::Point,
+  before:  Bound<A::Point>,
+  _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T, A> Overlapping<'a, T, A>
+  where T: Intrusive<Node<T, MaxEnd<T, A>>>, A: IntervalAdapter<T>
+{
+  #[inline]
+  fn new(root: *mut T, after: A::Point, before: Bound<A::Point>) -> Overlapping<'a, T, A> {
+    let mut iter = Overlapping { stack: Spine::new(), after, before, _marker: PhantomData };
+    iter.descend(root);
+    iter
+  }
+
+  /// Push the left spine of the subtree at `node`, as far down as anything
+  /// ends after the point.
+  #[inline]
+  fn descend(&mut self, mut node: *mut T) {
+    while ends_after(node, &self.after) {
+      self.stack.push(node);
+      node = node.field().left();
+    }
+  }
+}
+
+impl<'a, T, A> Iterator for Overlapping<'a, T, A>
+  where T: Intrusive<Node<T, MaxEnd<T, A>>>, A: IntervalAdapter<T>
+{
+  type Item = &'a T;
+
+  fn next(&mut self) -> Option<&'a T> {
+    loop {
+      let node = self.stack.peek();
+      let elem = unsafe { node.as_ref()? };
+      let started = match &self.before {
+        Included(before) => A::start(elem) <= before,
+        Excluded(before) => A::start(elem) < before,
+        Unbounded        => true,
+      };
+      if !started {
+        // Nor does anything after it
+        self.stack.clear();
+        return None
+      }
+      self.stack.pop();
+      self.descend(node.field().right());
+      if *A::end(elem) > self.after {
+        return Some(elem)
+      }
+    }
+  }
+}
+
+impl<'a, T, A> FusedIterator for Overlapping<'a, T, A>
+  where T: Intrusive<Node<T, MaxEnd<T, A>>>, A: IntervalAdapter<T> { }
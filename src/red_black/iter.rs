@@ -0,0 +1,632 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering::{Equal, Greater, Less};
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ops::Bound::{Excluded, Included, Unbounded};
+use core::ops::RangeBounds;
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use super::{summary, Augment, Compare, Counter, KeyAdapter, Links, Node, Size, Tree, PATH_LEN};
+
+
+/// The ancestors an in-order walk in one direction has yet to visit, nearest
+/// last. Each one's subtree on the near side is done with, or being visited.
+///
+/// No path through a tree is longer than `PATH_LEN`, so neither is this.
+pub(super) struct Spine<T, L> {
+  nodes:  [*mut T; PATH_LEN],
+  len:    usize,
+  _links: PhantomData<L>,
+}
+
+impl<T, L> Clone for Spine<T, L> {
+  #[inline]
+  fn clone(&self) -> Spine<T, L> {
+    Spine { nodes: self.nodes, len: self.len, _links: PhantomData }
+  }
+}
+
+impl<T, L> Spine<T, L> where T: Intrusive<L>, L: Links<T>
+{
+  #[inline]
+  pub(super) fn new() -> Spine<T, L> {
+    Spine {
+      nodes:  [ptr::null_mut(); PATH_LEN],
+      len:    0,
+      _links: PhantomData,
+    }
+  }
+
+  #[inline]
+  pub(super) fn push(&mut self, node: *mut T) {
+    self.nodes[self.len] = node;
+    self.len += 1;
+  }
+
+  #[inline]
+  pub(super) fn pop(&mut self) -> *mut T {
+    self.len -= 1;
+    self.nodes[self.len]
+  }
+
+  #[inline]
+  pub(super) fn clear(&mut self) {
+    self.len = 0;
+  }
+
+  /// The next element, or null.
+  #[inline]
+  pub(super) fn peek(&self) -> *mut T {
+    match self.len {
+      0 => ptr::null_mut(),
+      n => self.nodes[n - 1],
+    }
+  }
+
+  #[inline]
+  fn push_left(&mut self, mut node: *mut T) {
+    while !node.is_null() {
+      self.push(node);
+      node = node.field().left();
+    }
+  }
+
+  #[inline]
+  fn push_right(&mut self, mut node: *mut T) {
+    while !node.is_null() {
+      self.push(node);
+      node = node.field().right();
+    }
+  }
+
+  /// Descend from `node` towards the first element `above` holds for, keeping
+  /// those it holds for.
+  #[inline]
+  fn seek_front<F>(&mut self, mut node: *mut T, mut above: F) where F: FnMut(&T) -> bool {
+    while !node.is_null() {
+      if above(unsafe { &*node }) {
+        self.push(node);
+        node = node.field().left();
+      } else {
+        node = node.field().right();
+      }
+    }
+  }
+
+  /// Descend from `node` towards the last element `below` holds for, keeping
+  /// those it holds for.
+  #[inline]
+  fn seek_back<F>(&mut self, mut node: *mut T, mut below: F) where F: FnMut(&T) -> bool {
+    while !node.is_null() {
+      if below(unsafe { &*node }) {
+        self.push(node);
+        node = node.field().right();
+      } else {
+        node = node.field().left();
+      }
+    }
+  }
+
+  /// Take the next element of a forward walk. The spine must not be empty.
+  #[inline]
+  fn pop_front(&mut self) -> *mut T {
+    let node = self.pop();
+    self.push_left(node.field().right());
+    node
+  }
+
+  /// Take the next element of a backward walk. The spine must not be empty.
+  #[inline]
+  fn pop_back(&mut self) -> *mut T {
+    let node = self.pop();
+    self.push_right(node.field().left());
+    node
+  }
+}
+
+/// Both ends of an in-order walk, which stops once they meet.
+pub(super) struct Walk<T, S> {
+  front: Spine<T, Node<T, S>>,
+  back:  Spine<T, Node<T, S>>,
+}
+
+impl<T, S> Clone for Walk<T, S> {
+  #[inline]
+  fn clone(&self) -> Walk<T, S> {
+    Walk { front: self.front.clone(), back: self.back.clone() }
+  }
+}
+
+impl<T, S> Walk<T, S> where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  #[inline]
+  pub(super) fn new(root: *mut T) -> Walk<T, S> {
+    let mut walk = Walk { front: Spine::new(), back: Spine::new() };
+    walk.front.push_left(root);
+    walk.back.push_right(root);
+    walk
+  }
+
+  /// A walk over the elements both `above` and `below` hold for, which must
+  /// be a contiguous run.
+  #[inline]
+  fn bounded<F, G>(root: *mut T, above: F, mut below: G) -> Walk<T, S>
+    where F: FnMut(&T) -> bool, G: FnMut(&T) -> bool
+  {
+    let mut walk = Walk { front: Spine::new(), back: Spine::new() };
+    walk.front.seek_front(root, above);
+    walk.back.seek_back(root, &mut below);
+    // The first element past the lower bound may be past the upper one too
+    let first = walk.front.peek();
+    if first.is_null() || !below(unsafe { &*first }) {
+      walk.finish();
+    }
+    walk
+  }
+
+  #[inline]
+  fn finish(&mut self) {
+    self.front.clear();
+    self.back.clear();
+  }
+
+  #[inline]
+  pub(super) fn next(&mut self) -> *mut T {
+    let node = self.front.peek();
+    if node.is_null() {
+      return node
+    }
+    if node == self.back.peek() {
+      self.finish();
+      return node
+    }
+    self.front.pop_front()
+  }
+
+  #[inline]
+  fn next_back(&mut self) -> *mut T {
+    let node = self.back.peek();
+    if node.is_null() {
+      return node
+    }
+    if node == self.front.peek() {
+      self.finish();
+      return node
+    }
+    self.back.pop_back()
+  }
+}
+
+/// An in-order iterator over the elements of a tree.
+///
+/// It keeps both ends' ancestors in fixed arrays, so it takes no allocation
+/// and a constant `4 * usize::BITS` words, and steps in amortized O(1).
+pub struct Iter<'a, T, S = (), L = ()> {
+  walk:    Walk<T, S>,
+  len:     Option<usize>,
+  _marker: PhantomData<(&'a T, L)>,
+}
+
+/// An in-order iterator over pinned mutable references to the elements of a
+/// tree. They are pinned as moving an element would break the tree's links.
+pub struct IterMut<'a, T, S = (), L = ()> {
+  walk:    Walk<T, S>,
+  len:     Option<usize>,
+  _marker: PhantomData<(&'a mut T, L)>,
+}
+
+/// An in-order iterator over the elements of a tree within a range of keys.
+pub struct Range<'a, T, S = ()> {
+  walk:    Walk<T, S>,
+  _marker: PhantomData<&'a T>,
+}
+
+/// An in-order iterator over pinned mutable references to the elements of a
+/// tree within a range of keys.
+pub struct RangeMut<'a, T, S = ()> {
+  walk:    Walk<T, S>,
+  _marker: PhantomData<&'a mut T>,
+}
+
+/// Takes the elements out of a tree in post-order, so that each is handed out
+/// after its children. Those not taken are unlinked when it is dropped.
+pub struct Drain<'a, T, S = ()> where T: Intrusive<Node<T, S>>, S: Augment<T> {
+  // Elements whose subtrees are being taken apart, parents first.
+  stack:   Spine<T, Node<T, S>>,
+  _marker: PhantomData<&'a mut T>,
+}
+
+impl<T, A, C, S, L> Tree<T, A, C, S, L> where T: Intrusive<Node<T, S>>, S: Augment<T>, L: Counter
+{
+  /// The number of elements, if the tree or its nodes count them.
+  #[inline]
+  fn known_len(&self) -> Option<usize> {
+    self.len.get().or(summary(self.root).count())
+  }
+
+  /// The elements in order.
+  #[inline]
+  pub fn iter(&self) -> Iter<'_, T, S, L> {
+    Iter { walk: Walk::new(self.root), len: self.known_len(), _marker: PhantomData }
+  }
+
+  /// The elements in order, mutably. The keys must not be changed so as to
+  /// reorder them.
+  #[inline]
+  pub fn iter_mut(&mut self) -> IterMut<'_, T, S, L> {
+    IterMut { walk: Walk::new(self.root), len: self.known_len(), _marker: PhantomData }
+  }
+
+  /// Empty the tree, handing out each element once with its links reset, in
+  /// O(n) total as there is no rebalancing.
+  #[inline]
+  pub fn drain(&mut self) -> Drain<'_, T, S> {
+    let root = self.root;
+    self.root = ptr::null_mut();
+    self.len = L::ZERO;
+    Drain::new(root)
+  }
+
+  /// Empty the tree as for `drain`, calling `f` on each element.
+  #[inline]
+  pub fn clear_with<F>(&mut self, f: F) where F: FnMut(NonNull<T>) {
+    self.drain().for_each(f)
+  }
+}
+
+/// Panic as `BTreeMap::range` does if `range` runs backwards by `cmp`.
+pub(super) fn check_range<Q, C, R>(cmp: &C, range: &R) where C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q> {
+  match (range.start_bound(), range.end_bound()) {
+    (Excluded(start), Excluded(end)) if cmp.compare(start, end) == Equal =>
+      panic!("range start and end are equal and excluded"),
+    (Included(start) | Excluded(start), Included(end) | Excluded(end))
+      if cmp.compare(start, end) == Greater =>
+      panic!("range start is greater than range end"),
+    _ => (),
+  }
+}
+
+/// Whether `key` is past the start of `range`.
+#[inline]
+pub(super) fn above_start<K, Q, C, R>(cmp: &C, key: &K, range: &R) -> bool
+  where K: Borrow<Q> + ?Sized, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+{
+  match range.start_bound() {
+    Included(start) => cmp.compare(key.borrow(), start) != Less,
+    Excluded(start) => cmp.compare(key.borrow(), start) == Greater,
+    Unbounded       => true,
+  }
+}
+
+/// Whether `key` is short of the end of `range`.
+#[inline]
+pub(super) fn below_end<K, Q, C, R>(cmp: &C, key: &K, range: &R) -> bool
+  where K: Borrow<Q> + ?Sized, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+{
+  match range.end_bound() {
+    Included(end) => cmp.compare(key.borrow(), end) != Greater,
+    Excluded(end) => cmp.compare(key.borrow(), end) == Less,
+    Unbounded     => true,
+  }
+}
+
+impl<T, A, C, S, L> Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// Panic as `BTreeMap::range` does if `range` runs backwards.
+  #[inline]
+  pub(super) fn check_range<Q, R>(&self, range: &R)
+    where C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    check_range(&self.cmp, range)
+  }
+
+  /// Whether `elem` is past the start of `range`.
+  #[inline]
+  pub(super) fn above_start<Q, R>(&self, elem: &T, range: &R) -> bool
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    above_start(&self.cmp, A::key(elem), range)
+  }
+
+  /// Whether `elem` is short of the end of `range`.
+  #[inline]
+  pub(super) fn below_end<Q, R>(&self, elem: &T, range: &R) -> bool
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    below_end(&self.cmp, A::key(elem), range)
+  }
+
+  fn range_walk<Q, R>(&self, range: R) -> Walk<T, S>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    self.check_range(&range);
+    let above = |elem: &T| self.above_start(elem, &range);
+    let below = |elem: &T| self.below_end(elem, &range);
+    Walk::bounded(self.root, above, below)
+  }
+
+  /// The elements within `range` in order, as for `BTreeMap::range`.
+  ///
+  /// # Panics
+  ///
+  /// If the range starts after it ends, or starts and ends at the same
+  /// excluded key.
+  #[inline]
+  pub fn range<Q, R>(&self, range: R) -> Range<'_, T, S>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    Range { walk: self.range_walk(range), _marker: PhantomData }
+  }
+
+  /// The elements within `range` in order, mutably. The keys must not be
+  /// changed so as to reorder them.
+  ///
+  /// # Panics
+  ///
+  /// As for `range`.
+  #[inline]
+  pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, T, S>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    RangeMut { walk: self.range_walk(range), _marker: PhantomData }
+  }
+
+  /// The summary of the elements within `range`, as if they made up a tree
+  /// of their own, in O(log n) time.
+  ///
+  /// # Panics
+  ///
+  /// As for `range`.
+  pub fn fold_range<Q, R>(&self, range: R) -> S
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    self.check_range(&range);
+    fold_range(self.root, |elem| self.above_start(elem, &range), |elem| self.below_end(elem, &range))
+  }
+}
+
+/// The summary of the elements of the subtree at `root` that both `above` and
+/// `below` hold for, which must be a contiguous run.
+pub(super) fn fold_range<T, L, F, G>(root: *mut T, mut above: F, mut below: G) -> L::Summary
+  where T: Intrusive<L>, L: Links<T>, F: FnMut(&T) -> bool, G: FnMut(&T) -> bool
+{
+  // Down to the highest element within the range, under which it all is
+  let mut node = root;
+  loop {
+    let Some(elem) = (unsafe { node.as_ref() }) else {
+      return L::Summary::EMPTY
+    };
+    if !above(elem) {
+      node = node.field().right();
+    } else if !below(elem) {
+      node = node.field().left();
+    } else {
+      break
+    }
+  }
+  let left = fold_suffix(node.field().left(), above);
+  let right = fold_prefix(node.field().right(), below);
+  L::Summary::combine(left, unsafe { &*node }, right)
+}
+
+/// The summary of the elements of the subtree at `node` that `f` holds for,
+/// which must be a suffix of them.
+///
+/// Each element `f` holds for is in, with everything after it in the
+/// subtree; so going back up, each one's summary is folded in along with its
+/// right subtree's.
+fn fold_suffix<T, L, F>(mut node: *mut T, mut f: F) -> L::Summary
+  where T: Intrusive<L>, L: Links<T>, F: FnMut(&T) -> bool
+{
+  let mut spine = Spine::<T, L>::new();
+  while !node.is_null() {
+    if f(unsafe { &*node }) {
+      spine.push(node);
+      node = node.field().left();
+    } else {
+      node = node.field().right();
+    }
+  }
+  let mut acc = L::Summary::EMPTY;
+  while spine.len > 0 {
+    let node = spine.pop();
+    acc = L::Summary::combine(acc, unsafe { &*node }, summary(node.field().right()));
+  }
+  acc
+}
+
+/// As `fold_suffix`, for a prefix.
+fn fold_prefix<T, L, F>(mut node: *mut T, mut f: F) -> L::Summary
+  where T: Intrusive<L>, L: Links<T>, F: FnMut(&T) -> bool
+{
+  let mut spine = Spine::<T, L>::new();
+  while !node.is_null() {
+    if f(unsafe { &*node }) {
+      spine.push(node);
+      node = node.field().right();
+    } else {
+      node = node.field().left();
+    }
+  }
+  let mut acc = L::Summary::EMPTY;
+  while spine.len > 0 {
+    let node = spine.pop();
+    acc = L::Summary::combine(summary(node.field().left()), unsafe { &*node }, acc);
+  }
+  acc
+}
+
+/// Implement the iterator traits for a type holding a `Walk` over nodes of
+/// type `$node`, given how to turn the walk's nodes into items. Those marked
+/// `counted` also hold the number of elements left in `len`, if the tree or
+/// its nodes keep count, and so know their exact size in trees that count
+/// their elements or keep their subtrees' `Size`.
+macro_rules! walk_iterator {
+  ($node:ident, $iter:ident<$a:lifetime>, $item:ty, $get:expr) => {
+    impl<$a, T, S> Iterator for $iter<$a, T, S> where T: Intrusive<$node<T, S>>, S: Augment<T>
+    {
+      type Item = $item;
+
+      #[inline]
+      fn next(&mut self) -> Option<$item> {
+        ($get)(self.walk.next())
+      }
+    }
+
+    impl<$a, T, S> DoubleEndedIterator for $iter<$a, T, S> where T: Intrusive<$node<T, S>>, S: Augment<T>
+    {
+      #[inline]
+      fn next_back(&mut self) -> Option<$item> {
+        ($get)(self.walk.next_back())
+      }
+    }
+
+    impl<$a, T, S> FusedIterator for $iter<$a, T, S> where T: Intrusive<$node<T, S>>, S: Augment<T> { }
+  };
+  ($node:ident, $iter:ident<$a:lifetime>, $item:ty, $get:expr, counted) => {
+    impl<$a, T, S, L> Iterator for $iter<$a, T, S, L> where T: Intrusive<$node<T, S>>, S: Augment<T>
+    {
+      type Item = $item;
+
+      #[inline]
+      fn next(&mut self) -> Option<$item> {
+        let item = ($get)(self.walk.next())?;
+        if let Some(len) = &mut self.len {
+          *len -= 1;
+        }
+        Some(item)
+      }
+
+      #[inline]
+      fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.len {
+          Some(len) => (len, Some(len)),
+          None      => (0, None),
+        }
+      }
+    }
+
+    impl<$a, T, S, L> DoubleEndedIterator for $iter<$a, T, S, L> where T: Intrusive<$node<T, S>>, S: Augment<T>
+    {
+      #[inline]
+      fn next_back(&mut self) -> Option<$item> {
+        let item = ($get)(self.walk.next_back())?;
+        if let Some(len) = &mut self.len {
+          *len -= 1;
+        }
+        Some(item)
+      }
+    }
+
+    impl<$a, T> ExactSizeIterator for $iter<$a, T, Size> where T: Intrusive<$node<T, Size>> { }
+
+    impl<$a, T, S> ExactSizeIterator for $iter<$a, T, S, usize> where T: Intrusive<$node<T, S>>, S: Augment<T> { }
+
+    impl<$a, T, S, L> FusedIterator for $iter<$a, T, S, L> where T: Intrusive<$node<T, S>>, S: Augment<T> { }
+  };
+}
+
+// Each element is handed out once, and its links are read beforehand, so the
+// mutable references never alias.
+walk_iterator!(Node, Iter<'a>, &'a T, |node: *mut T| unsafe { node.as_ref() }, counted);
+walk_iterator!(Node, IterMut<'a>, Pin<&'a mut T>, |node: *mut T| unsafe {
+  node.as_mut().map(|node| Pin::new_unchecked(node))
+}, counted);
+walk_iterator!(Node, Range<'a>, &'a T, |node: *mut T| unsafe { node.as_ref() });
+walk_iterator!(Node, RangeMut<'a>, Pin<&'a mut T>, |node: *mut T| unsafe {
+  node.as_mut().map(|node| Pin::new_unchecked(node))
+});
+
+impl<'a, T, S, L> Clone for Iter<'a, T, S, L> {
+  #[inline]
+  fn clone(&self) -> Iter<'a, T, S, L> {
+    Iter { walk: self.walk.clone(), len: self.len, _marker: PhantomData }
+  }
+}
+
+impl<'a, T, S> Clone for Range<'a, T, S> {
+  #[inline]
+  fn clone(&self) -> Range<'a, T, S> {
+    Range { walk: self.walk.clone(), _marker: PhantomData }
+  }
+}
+
+impl<'a, T, A, C, S, L> IntoIterator for &'a Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, L: Counter
+{
+  type Item = &'a T;
+  type IntoIter = Iter<'a, T, S, L>;
+
+  #[inline]
+  fn into_iter(self) -> Iter<'a, T, S, L> {
+    self.iter()
+  }
+}
+
+impl<'a, T, A, C, S, L> IntoIterator for &'a mut Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, L: Counter
+{
+  type Item = Pin<&'a mut T>;
+  type IntoIter = IterMut<'a, T, S, L>;
+
+  #[inline]
+  fn into_iter(self) -> IterMut<'a, T, S, L> {
+    self.iter_mut()
+  }
+}
+
+impl<'a, T, S> Drain<'a, T, S> where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  /// Take apart the subtree at `root`, which nothing else may still link to.
+  #[inline]
+  pub(super) fn new(root: *mut T) -> Drain<'a, T, S> {
+    let mut stack = Spine::new();
+    if !root.is_null() {
+      stack.push(root);
+    }
+    Drain { stack, _marker: PhantomData }
+  }
+}
+
+impl<'a, T, S> Iterator for Drain<'a, T, S> where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  type Item = NonNull<T>;
+
+  fn next(&mut self) -> Option<NonNull<T>> {
+    loop {
+      let node = self.stack.peek();
+      if node.is_null() {
+        return None
+      }
+      // Cut each child off as it is descended into, so that a node is a leaf
+      // by the time it is come back to.
+      let left = node.field().left();
+      if !left.is_null() {
+        node.field().set_left(ptr::null_mut());
+        self.stack.push(left);
+        continue
+      }
+      let right = node.field().right();
+      if !right.is_null() {
+        node.field().set_right(ptr::null_mut());
+        self.stack.push(right);
+        continue
+      }
+      self.stack.pop();
+      node.field().reset(false);
+      return NonNull::new(node)
+    }
+  }
+}
+
+impl<'a, T, S> FusedIterator for Drain<'a, T, S> where T: Intrusive<Node<T, S>>, S: Augment<T> { }
+
+impl<'a, T, S> Drop for Drain<'a, T, S> where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  fn drop(&mut self) {
+    self.for_each(drop)
+  }
+}
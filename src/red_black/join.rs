@@ -0,0 +1,279 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering::{Greater, Less};
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use super::iter::{Drain, Walk};
+use super::{graft, is_red, update, Augment, Compare, Counter, KeyAdapter, Links, Node, PathElem, Tree, TreeError, PATH_LEN};
+
+
+/// A subtree standing as a tree of its own, with a black root, and its black
+/// height.
+pub(super) type Part<T> = (*mut T, usize);
+
+/// What splitting and joining trees needs of a kind of node, beyond its
+/// links, so that `Tree` and `ParentTree` can share it.
+pub(super) trait Join<T>: Links<T> + Sized {
+  /// Cut a subtree of black height `height` loose as a tree of its own, whose
+  /// root must be black.
+  fn detach(node: *mut T, height: usize) -> Part<T> where T: Intrusive<Self>;
+
+  /// Join the trees rooted at `left` and `right`, of black heights
+  /// `left_height` and `right_height`, with `pivot` between them. Returns the
+  /// new root and its black height.
+  ///
+  /// The shorter tree hangs off the taller one's near spine at the level of
+  /// the same black height, under `pivot`, which is rebalanced up from there
+  /// as an insertion would be. This is O(|left_height - right_height| + 1).
+  unsafe fn join_roots(left: *mut T, left_height: usize, pivot: *mut T,
+                       right: *mut T, right_height: usize) -> Part<T>
+    where T: Intrusive<Self>;
+
+  /// Leave `node` as unlinked as a new one.
+  fn unlink(node: *mut T) where T: Intrusive<Self>;
+
+  /// Unlink every element of the tree at `root`, handing each to `f`.
+  fn drain<F>(root: *mut T, f: F) where T: Intrusive<Self>, F: FnMut(NonNull<T>);
+}
+
+/// The number of black nodes on any path down from `node`, not counting the
+/// null leaves.
+#[inline]
+pub(super) fn black_height<T, L>(mut node: *mut T) -> usize where T: Intrusive<L>, L: Links<T> {
+  let mut height = 0;
+  while !node.is_null() {
+    height += !node.field().color() as usize;
+    node = node.field().left();
+  }
+  height
+}
+
+impl<T, S> Join<T> for Node<T, S> where S: Augment<T>
+{
+  #[inline]
+  fn detach(node: *mut T, height: usize) -> Part<T> where T: Intrusive<Node<T, S>> {
+    if is_red(node) {
+      node.field().set_color(false);
+      (node, height + 1)
+    } else {
+      (node, height)
+    }
+  }
+
+  // Kept out of line, like `split_by`, so that the path is not in the frames
+  // of the recursive set operations.
+  #[inline(never)]
+  unsafe fn join_roots(left: *mut T, left_height: usize, pivot: *mut T,
+                       right: *mut T, right_height: usize) -> Part<T>
+    where T: Intrusive<Node<T, S>>
+  {
+    if left_height == right_height {
+      pivot.field().reset(false);
+      pivot.field().set_left(left);
+      pivot.field().set_right(right);
+      update(pivot);
+      return (pivot, left_height + 1)
+    }
+    let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+    let mut i = 0;
+    if left_height > right_height {
+      // Right links are all black, so each step down one loses one
+      path[0].node = left;
+      for _ in right_height..left_height {
+        path[i].cmp = Greater;
+        path[i + 1].node = path[i].node.field().right();
+        i += 1;
+      }
+      pivot.field().reset(true);
+      pivot.field().set_left(path[i].node);
+      pivot.field().set_right(right);
+      let grew = graft(&mut path, i, pivot);
+      (path[0].node, left_height + grew as usize)
+    } else {
+      // Left links may be red, so stop only at a black node
+      path[0].node = right;
+      let mut height = right_height;
+      while height != left_height || is_red(path[i].node) {
+        height -= !is_red(path[i].node) as usize;
+        path[i].cmp = Less;
+        path[i + 1].node = path[i].node.field().left();
+        i += 1;
+      }
+      pivot.field().reset(true);
+      pivot.field().set_left(left);
+      pivot.field().set_right(path[i].node);
+      let grew = graft(&mut path, i, pivot);
+      (path[0].node, right_height + grew as usize)
+    }
+  }
+
+  #[inline]
+  fn unlink(node: *mut T) where T: Intrusive<Node<T, S>> {
+    node.field().reset(false);
+  }
+
+  #[inline]
+  fn drain<F>(root: *mut T, f: F) where T: Intrusive<Node<T, S>>, F: FnMut(NonNull<T>) {
+    Drain::new(root).for_each(f)
+  }
+}
+
+/// Split the tree rooted at `root` into the elements `f` holds for and those
+/// after them, in O(log n) time. `f` must hold for a prefix of the elements.
+///
+/// Corrupt links are found on the way down, before anything is changed.
+#[inline(never)]
+pub(super) unsafe fn split_by<T, L, F>(root: *mut T, mut f: F) -> Result<(Part<T>, Part<T>), TreeError>
+  where T: Intrusive<L>, L: Join<T>, F: FnMut(&T) -> bool
+{
+  // Record the path down to the leaf between the two halves
+  let mut path = [PathElem::new(ptr::null_mut()); PATH_LEN];
+  let mut i = 0;
+  path[0].node = root;
+  while !path[i].node.is_null() {
+    let cur = path[i].node;
+    path[i].cmp = if f(&*cur) { Greater } else { Less };
+    ensure!(i + 1 < path.len());
+    path[i + 1].node = match path[i].cmp {
+      Less => cur.field().left(),
+      _    => cur.field().right(),
+    };
+    i += 1;
+  }
+
+  // Going back up, each element joins one half with the subtree on its side
+  // of the path. The heights joined grow as they go, so this all comes to
+  // O(log n).
+  let mut left = (ptr::null_mut(), 0);
+  let mut right = (ptr::null_mut(), 0);
+  let mut height = 0;
+  while i > 0 {
+    i -= 1;
+    let cur = path[i].node;
+    let black = !cur.field().color();
+    if path[i].cmp == Greater {
+      let sub = L::detach(cur.field().left(), height);
+      left = L::join_roots(sub.0, sub.1, cur, left.0, left.1);
+    } else {
+      let sub = L::detach(cur.field().right(), height);
+      right = L::join_roots(right.0, right.1, cur, sub.0, sub.1);
+    }
+    height += black as usize;
+  }
+  Ok((left, right))
+}
+
+/// The number of elements in the smaller of the trees rooted at `a` and `b`,
+/// and whether that is `a`'s, in time proportional to it.
+fn count_smaller<T, S>(a: *mut T, b: *mut T) -> (usize, bool)
+  where T: Intrusive<Node<T, S>>, S: Augment<T>
+{
+  let (mut a, mut b) = (Walk::<T, S>::new(a), Walk::<T, S>::new(b));
+  let mut n = 0;
+  loop {
+    if a.next().is_null() {
+      return (n, true)
+    }
+    if b.next().is_null() {
+      return (n, false)
+    }
+    n += 1;
+  }
+}
+
+impl<T, A, C, S, L> Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// A tree of the elements of `left`, then `pivot`, then those of `right`,
+  /// in O(log n) time. It keeps `left`'s comparator.
+  ///
+  /// No element of `left` may be greater than `pivot`, nor `pivot` greater
+  /// than any of `right`. This is only checked in debug builds.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`, for `pivot`.
+  pub unsafe fn join(left: Tree<T, A, C, S, L>, pivot: NonNull<T>, right: Tree<T, A, C, S, L>)
+                     -> Tree<T, A, C, S, L>
+  {
+    let pivot = pivot.as_ptr();
+    debug_assert!(left.last().is_none_or(|last| left.order(last.as_ref(), &*pivot) != Greater),
+                  "join: left tree is not before pivot");
+    debug_assert!(right.first().is_none_or(|first| left.order(&*pivot, first.as_ref()) != Greater),
+                  "join: right tree is not after pivot");
+    let (root, _) = Node::join_roots(left.root, black_height(left.root), pivot,
+                                     right.root, black_height(right.root));
+    let mut len = left.len;
+    len.add(1 + right.len.get().unwrap_or(0));
+    Tree { root, len, cmp: left.cmp, _adapter: PhantomData }
+  }
+
+  /// Move every element of `other` into this tree, leaving `other` empty.
+  ///
+  /// If all of `other` goes after all of this tree or before it, this is
+  /// O(log n); otherwise the elements are inserted one by one.
+  pub fn append(&mut self, other: &mut Tree<T, A, C, S, L>) {
+    let Some(first) = other.first() else {
+      return
+    };
+    let Some(last) = self.last() else {
+      mem::swap(&mut self.root, &mut other.root);
+      mem::swap(&mut self.len, &mut other.len);
+      return
+    };
+    unsafe {
+      if self.order(last.as_ref(), first.as_ref()) != Greater {
+        other.remove(first);
+        self.root = Node::join_roots(self.root, black_height(self.root), first.as_ptr(),
+                                     other.root, black_height(other.root)).0;
+        self.len.add(1 + mem::replace(&mut other.len, L::ZERO).get().unwrap_or(0));
+        other.root = ptr::null_mut();
+        return
+      }
+      let (first, last) = (self.first().unwrap(), other.last().unwrap());
+      if self.order(last.as_ref(), first.as_ref()) == Less {
+        other.remove(last);
+        self.root = Node::join_roots(other.root, black_height(other.root), last.as_ptr(),
+                                     self.root, black_height(self.root)).0;
+        self.len.add(1 + mem::replace(&mut other.len, L::ZERO).get().unwrap_or(0));
+        other.root = ptr::null_mut();
+        return
+      }
+      // Interleaved, so in order to keep equal keys in insertion order
+      while let Some(node) = other.first() {
+        other.remove(node);
+        self.insert(node);
+      }
+    }
+  }
+
+  /// Move every element not less than `key` into a new tree, in O(log n)
+  /// time. A tree that counts its elements must count those on whichever
+  /// side is smaller too, so for it this is O(log n + min(k, n - k)) for k
+  /// elements moved.
+  #[inline]
+  pub fn split_off<Q>(&mut self, key: &Q) -> Tree<T, A, C, S, L>
+    where A::Key: Borrow<Q>, C: Compare<Q> + Clone, Q: ?Sized
+  {
+    self.try_split_off(key).unwrap_or_else(|err| panic!("{}", err))
+  }
+
+  /// As `split_off`, or report corrupt links, leaving the tree as it was.
+  pub fn try_split_off<Q>(&mut self, key: &Q) -> Result<Tree<T, A, C, S, L>, TreeError>
+    where A::Key: Borrow<Q>, C: Compare<Q> + Clone, Q: ?Sized
+  {
+    let f = |elem: &T| self.cmp.compare(A::key(elem).borrow(), key) == Less;
+    let (left, right) = unsafe { split_by(self.root, f)? };
+    self.root = left.0;
+    let mut len = L::ZERO;
+    if let Some(n) = self.len.get() {
+      let (smaller, left_is) = count_smaller(left.0, right.0);
+      let moved = if left_is { n - smaller } else { smaller };
+      self.len.sub(moved);
+      len.add(moved);
+    }
+    Ok(Tree { root: right.0, len, cmp: self.cmp.clone(), _adapter: PhantomData })
+  }
+}
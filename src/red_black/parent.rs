@@ -0,0 +1,1282 @@
+//! Red-black trees whose nodes also point to their parents, so that an
+//! element can be unlinked, or stepped from, without a search from the root.
+//!
+//! A `ParentNode<T>` is three words to `Node<T>`'s two, with the color packed
+//! into the parent pointer. The balancing is the classic red-black scheme
+//! rather than the left-leaning one, as it needs no path to rebalance up.
+//! Searching, folding, splitting, joining and the set operations are shared
+//! with `Tree` through the `Links` and `Join` traits.
+
+use core::borrow::Borrow;
+use core::cell::Cell;
+use core::cmp::Ordering::{self, Equal, Greater, Less};
+use core::marker::{PhantomData, PhantomPinned};
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use crate::aligned_ptr_pun::AlignedPtrPun;
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use super::{find_first_where_, is_red, leftmost, nsearch_, psearch_, rank_, rightmost, search_, select_, summary,
+            update, usearch_, Augment, Compare, Counter, KeyAdapter, Links, Natural, Size, TreeError, PATH_LEN};
+
+pub use self::cursor::{Cursor, CursorMut, ExtractIf};
+pub use self::entry::{Entry, OccupiedEntry, VacantEntry};
+pub use self::iter::{Drain, Iter, IterMut, Range, RangeMut};
+
+mod bulk;
+mod cursor;
+mod entry;
+mod iter;
+mod join;
+mod set;
+
+
+/// The fields required to be in a node to store it in a `ParentTree`.
+///
+/// As with `Node`, add this to your type, T, and implement
+/// `Intrusive<ParentNode<T>>`. It may likewise keep a summary `S` of its
+/// subtree, and is likewise `!Unpin`.
+pub struct ParentNode<T, S = ()> {
+  left:       Cell<*mut T>,
+  right:      Cell<*mut T>,
+  parent_red: Cell<AlignedPtrPun<T>>,
+  summary:    Cell<S>,
+  _pinned:    PhantomPinned,
+}
+
+impl<T, S> Default for ParentNode<T, S> where S: Augment<T> {
+  #[inline]
+  fn default() -> ParentNode<T, S> {
+    ParentNode::new()
+  }
+}
+
+impl<T, S> ParentNode<T, S> where S: Augment<T>
+{
+  /// An unlinked node.
+  #[inline]
+  pub const fn new() -> ParentNode<T, S> {
+    ParentNode {
+      left:       Cell::new(ptr::null_mut()),
+      right:      Cell::new(ptr::null_mut()),
+      parent_red: Cell::new(AlignedPtrPun::null()),
+      summary:    Cell::new(S::EMPTY),
+      _pinned:    PhantomPinned,
+    }
+  }
+
+  /// The summary of the subtree under this node.
+  #[inline]
+  pub fn summary(&self) -> S {
+    self.summary.get()
+  }
+
+  /// Leave the node as unlinked as a new one.
+  #[inline]
+  fn clear(&self) {
+    self.reset(ptr::null_mut(), false);
+    self.summary.set(S::EMPTY);
+  }
+}
+
+impl<T, S> ParentNode<T, S>
+{
+  #[inline]
+  fn reset(&self, parent: *mut T, color: bool) {
+    self.left.set(ptr::null_mut());
+    self.right.set(ptr::null_mut());
+    self.parent_red.set(AlignedPtrPun::new(parent, color));
+  }
+
+  #[inline]
+  fn left(&self) -> *mut T {
+    self.left.get()
+  }
+
+  #[inline]
+  fn set_left(&self, ptr: *mut T) {
+    self.left.set(ptr)
+  }
+
+  #[inline]
+  fn right(&self) -> *mut T {
+    self.right.get()
+  }
+
+  #[inline]
+  fn set_right(&self, ptr: *mut T) {
+    self.right.set(ptr)
+  }
+
+  #[inline]
+  fn parent(&self) -> *mut T {
+    self.parent_red.get().eliminate().0
+  }
+
+  #[inline]
+  fn set_parent(&self, ptr: *mut T) {
+    let mut parent_red = self.parent_red.get();
+    parent_red.set_ptr(ptr);
+    self.parent_red.set(parent_red)
+  }
+
+  /// `true` is red, `false` is black.
+  #[inline]
+  fn color(&self) -> bool {
+    self.parent_red.get().eliminate().1
+  }
+
+  #[inline]
+  fn set_color(&self, color: bool) {
+    let mut parent_red = self.parent_red.get();
+    parent_red.set_flag(color);
+    self.parent_red.set(parent_red)
+  }
+}
+
+impl<T, S> Links<T> for ParentNode<T, S> where S: Augment<T>
+{
+  type Summary = S;
+
+  #[inline]
+  fn left(&self) -> *mut T {
+    ParentNode::left(self)
+  }
+
+  #[inline]
+  fn right(&self) -> *mut T {
+    ParentNode::right(self)
+  }
+
+  #[inline]
+  fn color(&self) -> bool {
+    ParentNode::color(self)
+  }
+
+  #[inline]
+  fn summary(&self) -> S {
+    self.summary.get()
+  }
+
+  #[inline]
+  fn set_summary(&self, summary: S) {
+    self.summary.set(summary)
+  }
+}
+
+/// Point `child`, which may be a leaf, back up at `parent`.
+#[inline]
+fn adopt<T, S>(parent: *mut T, child: *mut T) where T: Intrusive<ParentNode<T, S>> {
+  if !child.is_null() {
+    child.field().set_parent(parent);
+  }
+}
+
+/// The in-order successor of `node`, or null. Over a whole walk each link is
+/// crossed twice, so this is amortized O(1).
+#[inline]
+fn successor<T, S>(mut node: *mut T) -> *mut T where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  let right = node.field().right();
+  if !right.is_null() {
+    return leftmost(right)
+  }
+  let mut parent = node.field().parent();
+  while !parent.is_null() && parent.field().right() == node {
+    node = parent;
+    parent = node.field().parent();
+  }
+  parent
+}
+
+/// As `successor`, for the predecessor.
+#[inline]
+fn predecessor<T, S>(mut node: *mut T) -> *mut T where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  let left = node.field().left();
+  if !left.is_null() {
+    return rightmost(left)
+  }
+  let mut parent = node.field().parent();
+  while !parent.is_null() && parent.field().left() == node {
+    node = parent;
+    parent = node.field().parent();
+  }
+  parent
+}
+
+/// Recompute the summaries of `node` and its ancestors, whose subtrees have
+/// changed below them.
+#[inline]
+fn update_up<T, S>(mut node: *mut T) where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  // Skipped outright, so that unlinking stays amortized O(1) without one
+  if mem::size_of::<S>() == 0 {
+    return
+  }
+  while !node.is_null() {
+    update(node);
+    node = node.field().parent();
+  }
+}
+
+// Restructuring. These take the root of the tree being worked on, rather
+// than the tree, so that `join_roots` can use them on bare subtrees.
+
+/// Point whichever child link of `parent` leads to `old` at `new`, or make
+/// `new` the root if `old` was.
+#[inline]
+fn replace_child<T, S>(root: &mut *mut T, parent: *mut T, old: *mut T, new: *mut T)
+  where T: Intrusive<ParentNode<T, S>>
+{
+  if parent.is_null() {
+    *root = new;
+  } else if parent.field().left() == old {
+    parent.field().set_left(new);
+  } else {
+    parent.field().set_right(new);
+  }
+}
+
+/// Put the subtree at `new` in place of that at `old`.
+#[inline]
+fn transplant<T, S>(root: &mut *mut T, old: *mut T, new: *mut T) where T: Intrusive<ParentNode<T, S>> {
+  let parent = old.field().parent();
+  replace_child(root, parent, old, new);
+  adopt(parent, new);
+}
+
+#[inline]
+fn rotate_left<T, S>(root: &mut *mut T, node: *mut T) where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  let right = node.field().right();
+  node.field().set_right(right.field().left());
+  adopt(node, right.field().left());
+  transplant(root, node, right);
+  right.field().set_left(node);
+  node.field().set_parent(right);
+  update(node);
+  update(right);
+}
+
+#[inline]
+fn rotate_right<T, S>(root: &mut *mut T, node: *mut T) where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  let left = node.field().left();
+  node.field().set_left(left.field().right());
+  adopt(node, left.field().right());
+  transplant(root, node, left);
+  left.field().set_right(node);
+  node.field().set_parent(left);
+  update(node);
+  update(left);
+}
+
+/// Rebalance after the red `node` was linked in, then blacken the root.
+/// Returns whether that grew the black height, as it does when a 4-node is
+/// split at the root. Summaries above `node` are left to the caller.
+fn link_fixup<T, S>(root: &mut *mut T, mut node: *mut T) -> bool
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>
+{
+  while is_red(node.field().parent()) {
+    // A red parent is never the root, so there is a grandparent
+    let mut parent = node.field().parent();
+    let grand = parent.field().parent();
+    if parent == grand.field().left() {
+      let uncle = grand.field().right();
+      if is_red(uncle) {
+        // Split 4-node
+        parent.field().set_color(false);
+        uncle.field().set_color(false);
+        grand.field().set_color(true);
+        node = grand;
+        continue
+      }
+      if node == parent.field().right() {
+        rotate_left(root, parent);
+        node = parent;
+        parent = node.field().parent();
+      }
+      parent.field().set_color(false);
+      grand.field().set_color(true);
+      rotate_right(root, grand);
+    } else {
+      let uncle = grand.field().left();
+      if is_red(uncle) {
+        parent.field().set_color(false);
+        uncle.field().set_color(false);
+        grand.field().set_color(true);
+        node = grand;
+        continue
+      }
+      if node == parent.field().left() {
+        rotate_right(root, parent);
+        node = parent;
+        parent = node.field().parent();
+      }
+      parent.field().set_color(false);
+      grand.field().set_color(true);
+      rotate_left(root, grand);
+    }
+  }
+  let grew = is_red(*root);
+  root.field().set_color(false);
+  grew
+}
+
+/// Restore the black height after a black node was spliced out from above
+/// `node`, whose parent is now `parent`.
+fn unlink_fixup<T, S>(root: &mut *mut T, mut node: *mut T, mut parent: *mut T)
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>
+{
+  while node != *root && !is_red(node) {
+    if node == parent.field().left() {
+      let mut sibling = parent.field().right();
+      if is_red(sibling) {
+        sibling.field().set_color(false);
+        parent.field().set_color(true);
+        rotate_left(root, parent);
+        sibling = parent.field().right();
+      }
+      if !is_red(sibling.field().left()) && !is_red(sibling.field().right()) {
+        sibling.field().set_color(true);
+        node = parent;
+        parent = node.field().parent();
+        continue
+      }
+      if !is_red(sibling.field().right()) {
+        sibling.field().left().field().set_color(false);
+        sibling.field().set_color(true);
+        rotate_right(root, sibling);
+        sibling = parent.field().right();
+      }
+      sibling.field().set_color(parent.field().color());
+      parent.field().set_color(false);
+      sibling.field().right().field().set_color(false);
+      rotate_left(root, parent);
+    } else {
+      let mut sibling = parent.field().left();
+      if is_red(sibling) {
+        sibling.field().set_color(false);
+        parent.field().set_color(true);
+        rotate_right(root, parent);
+        sibling = parent.field().left();
+      }
+      if !is_red(sibling.field().left()) && !is_red(sibling.field().right()) {
+        sibling.field().set_color(true);
+        node = parent;
+        parent = node.field().parent();
+        continue
+      }
+      if !is_red(sibling.field().left()) {
+        sibling.field().right().field().set_color(false);
+        sibling.field().set_color(true);
+        rotate_left(root, sibling);
+        sibling = parent.field().left();
+      }
+      sibling.field().set_color(parent.field().color());
+      parent.field().set_color(false);
+      sibling.field().left().field().set_color(false);
+      rotate_right(root, parent);
+    }
+    // Balance restored
+    node = *root;
+  }
+  if !node.is_null() {
+    node.field().set_color(false);
+  }
+}
+
+/// An intrusive red-black tree whose nodes point to their parents.
+///
+/// It has the same interface as `Tree`, but `remove`, `next`, `prev` and
+/// cursors work from an element's own links rather than a path down from the
+/// root, so none of them search. Like `Tree`, it holds nothing but its root,
+/// and a count of its elements if `L` is `usize`, and may be moved freely.
+pub struct ParentTree<T, A, C = Natural, S = (), L = ()> {
+  root:     *mut T,
+  len:      L,
+  cmp:      C,
+  _adapter: PhantomData<(A, S)>,
+}
+
+impl<T, A, S, L> ParentTree<T, A, Natural, S, L> where L: Counter
+{
+  /// An empty tree, ordered by its keys' own `Ord`.
+  #[inline]
+  pub const fn new() -> ParentTree<T, A, Natural, S, L> {
+    ParentTree::with_comparator(Natural)
+  }
+}
+
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L> where L: Counter
+{
+  /// An empty tree ordered by `cmp`.
+  #[inline]
+  pub const fn with_comparator(cmp: C) -> ParentTree<T, A, C, S, L> {
+    ParentTree {
+      root:     ptr::null_mut(),
+      len:      L::ZERO,
+      cmp,
+      _adapter: PhantomData,
+    }
+  }
+
+  #[inline]
+  pub fn comparator(&self) -> &C {
+    &self.cmp
+  }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.root.is_null()
+  }
+}
+
+impl<T, A, C, S, L> Default for ParentTree<T, A, C, S, L> where C: Default, L: Counter
+{
+  #[inline]
+  fn default() -> ParentTree<T, A, C, S, L> {
+    ParentTree::with_comparator(C::default())
+  }
+}
+
+impl<T, A, C, S> ParentTree<T, A, C, S, usize>
+{
+  /// The number of elements, in O(1) time.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.len
+  }
+}
+
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  #[inline]
+  fn order(&self, a: &T, b: &T) -> Ordering {
+    self.cmp.compare(A::key(a), A::key(b))
+  }
+
+  #[inline]
+  pub fn first(&self) -> Option<NonNull<T>> {
+    NonNull::new(leftmost(self.root))
+  }
+
+  #[inline]
+  pub fn last(&self) -> Option<NonNull<T>> {
+    NonNull::new(rightmost(self.root))
+  }
+
+  /// The element following `node`, in amortized O(1) time.
+  ///
+  /// # Safety
+  ///
+  /// `node` must be linked into this tree.
+  #[inline]
+  pub unsafe fn next(&self, node: NonNull<T>) -> Option<NonNull<T>> {
+    NonNull::new(successor(node.as_ptr()))
+  }
+
+  /// The element preceding `node`, in amortized O(1) time.
+  ///
+  /// # Safety
+  ///
+  /// `node` must be linked into this tree.
+  #[inline]
+  pub unsafe fn prev(&self, node: NonNull<T>) -> Option<NonNull<T>> {
+    NonNull::new(predecessor(node.as_ptr()))
+  }
+
+  /// The element following `node`, or `NotLinked` if `node` is not in this
+  /// tree, which is checked first in O(log n) time.
+  ///
+  /// # Safety
+  ///
+  /// `node` must point to a live element, though it need not be linked into
+  /// this tree.
+  pub unsafe fn try_next(&self, node: NonNull<T>) -> Result<Option<NonNull<T>>, TreeError> {
+    self.check_linked(node.as_ptr())?;
+    Ok(NonNull::new(successor(node.as_ptr())))
+  }
+
+  /// The element preceding `node`, or `NotLinked` if `node` is not in this
+  /// tree.
+  ///
+  /// # Safety
+  ///
+  /// As for `try_next`.
+  pub unsafe fn try_prev(&self, node: NonNull<T>) -> Result<Option<NonNull<T>>, TreeError> {
+    self.check_linked(node.as_ptr())?;
+    Ok(NonNull::new(predecessor(node.as_ptr())))
+  }
+
+  /// Climb from `node` to the root, checking that each parent links back
+  /// down to the child come from.
+  fn check_linked(&self, node: *mut T) -> Result<(), TreeError> {
+    let mut cur = node;
+    for _ in 0..PATH_LEN {
+      let parent = cur.field().parent();
+      if parent.is_null() {
+        return if cur == self.root { Ok(()) } else { Err(TreeError::NotLinked) }
+      }
+      ensure!(parent.field().left() == cur || parent.field().right() == cur);
+      cur = parent;
+    }
+    Err(TreeError::CorruptLinks)
+  }
+
+  /// An element equal to `key`.
+  #[inline]
+  pub fn search<Q>(&self, key: &Q) -> Option<NonNull<T>>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    NonNull::new(search_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key)))
+  }
+
+  /// The first element not less than `key`.
+  #[inline]
+  pub fn nsearch<Q>(&self, key: &Q) -> Option<NonNull<T>>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    NonNull::new(nsearch_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key)))
+  }
+
+  /// The last element not greater than `key`.
+  #[inline]
+  pub fn psearch<Q>(&self, key: &Q) -> Option<NonNull<T>>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    NonNull::new(psearch_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key)))
+  }
+
+  /// An element equal to `key`.
+  #[inline]
+  pub fn find<Q>(&self, key: &Q) -> Option<&T>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    self.find_by(|elem| self.cmp.compare(A::key(elem).borrow(), key))
+  }
+
+  /// The first element not less than `key`.
+  #[inline]
+  pub fn lower_bound<Q>(&self, key: &Q) -> Option<&T>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    self.lower_bound_by(|elem| self.cmp.compare(A::key(elem).borrow(), key))
+  }
+
+  /// The first element greater than `key`.
+  #[inline]
+  pub fn upper_bound<Q>(&self, key: &Q) -> Option<&T>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    self.upper_bound_by(|elem| self.cmp.compare(A::key(elem).borrow(), key))
+  }
+
+  /// The first and last elements equal to `key`, if there are any. Equal
+  /// elements are kept in the order they were inserted.
+  #[inline]
+  pub fn equal_range<Q>(&self, key: &Q) -> Option<(NonNull<T>, NonNull<T>)>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    let f = |elem: &T| self.cmp.compare(A::key(elem).borrow(), key);
+    let first = NonNull::new(nsearch_(self.root, f))?;
+    if f(unsafe { first.as_ref() }) != Equal {
+      return None
+    }
+    Some((first, NonNull::new(psearch_(self.root, f))?))
+  }
+
+  /// The number of elements equal to `key`, in O(log n + k) time.
+  pub fn count<Q>(&self, key: &Q) -> usize
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    let f = |elem: &T| self.cmp.compare(A::key(elem).borrow(), key);
+    let mut n = 0;
+    let mut node = nsearch_(self.root, f);
+    while !node.is_null() && f(unsafe { &*node }) == Equal {
+      n += 1;
+      node = successor(node);
+    }
+    n
+  }
+
+  /// The element for which `f` returns `Equal`. `f` reports how an element
+  /// compares to the target, and must agree with the tree's order.
+  #[inline]
+  pub fn find_by<F>(&self, f: F) -> Option<&T> where F: FnMut(&T) -> Ordering {
+    unsafe { search_(self.root, f).as_ref() }
+  }
+
+  /// The first element for which `f` does not return `Less`.
+  #[inline]
+  pub fn lower_bound_by<F>(&self, f: F) -> Option<&T> where F: FnMut(&T) -> Ordering {
+    unsafe { nsearch_(self.root, f).as_ref() }
+  }
+
+  /// The first element for which `f` returns `Greater`.
+  #[inline]
+  pub fn upper_bound_by<F>(&self, f: F) -> Option<&T> where F: FnMut(&T) -> Ordering {
+    unsafe { usearch_(self.root, f).as_ref() }
+  }
+
+  /// The first element whose own summary `f` holds for, as for
+  /// `Tree::find_first_where`, in O(log n) time.
+  #[inline]
+  pub fn find_first_where<F>(&self, f: F) -> Option<&T> where F: FnMut(S) -> bool {
+    unsafe { find_first_where_(self.root, f).as_ref() }
+  }
+
+  /// Find the leaf where an element belongs, given how each element compares
+  /// to it as for the searches: after any equal elements, or if `unique`, at
+  /// the first equal element met instead. Returns the leaf's parent and side,
+  /// or the equal element.
+  #[inline]
+  fn wind<F>(&self, mut f: F, unique: bool) -> Result<(*mut T, Ordering), *mut T> where F: FnMut(&T) -> Ordering {
+    let mut parent = ptr::null_mut();
+    let mut side = Equal;
+    let mut cur = self.root;
+    while !cur.is_null() {
+      side = match f(unsafe { &*cur }) {
+        Equal if unique => return Err(cur),
+        Greater         => Less,
+        Less | Equal    => Greater,
+      };
+      parent = cur;
+      cur = match side {
+        Less => cur.field().left(),
+        _    => cur.field().right(),
+      };
+    }
+    Ok((parent, side))
+  }
+
+  /// Link `node` in as `parent`'s child on `side`, or as the root if `parent`
+  /// is null, and rebalance.
+  unsafe fn link(&mut self, parent: *mut T, side: Ordering, node: *mut T) {
+    node.field().reset(parent, true);
+    if parent.is_null() {
+      self.root = node;
+    } else if side == Less {
+      parent.field().set_left(node);
+    } else {
+      parent.field().set_right(node);
+    }
+    update(node);
+    link_fixup(&mut self.root, node);
+    update_up(node);
+    self.len.add(1);
+  }
+
+  /// Put `node` in place of `old`, with the same links and color. The evicted
+  /// element is left as unlinked as a new one.
+  unsafe fn replace(&mut self, old: *mut T, node: *mut T) {
+    let links = old.field();
+    node.field().reset(links.parent(), links.color());
+    node.field().set_left(links.left());
+    node.field().set_right(links.right());
+    replace_child(&mut self.root, links.parent(), old, node);
+    adopt(node, links.left());
+    adopt(node, links.right());
+    update_up(node);
+    links.clear();
+  }
+
+  /// Link `node` into the tree, after any elements with an equal key.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not already be linked into a tree, and must stay put and
+  /// alive until it is removed.
+  #[inline]
+  pub unsafe fn insert(&mut self, node: NonNull<T>) {
+    self.try_insert(node).unwrap_or_else(|err| panic!("{}", err))
+  }
+
+  /// Link `node` into the tree, after any elements with an equal key, or
+  /// report why it can't be.
+  ///
+  /// `node` is checked for being linked already by climbing its parent links,
+  /// which takes O(log n) time like the search for its place.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not be linked into another tree, and must stay put and
+  /// alive until it is removed.
+  pub unsafe fn try_insert(&mut self, node: NonNull<T>) -> Result<(), TreeError> {
+    let node = node.as_ptr();
+    if self.order(&*node, &*node) != Equal {
+      return Err(TreeError::IncomparableKey)
+    }
+    match self.check_linked(node) {
+      Ok(())                    => return Err(TreeError::DuplicateKey),
+      Err(TreeError::NotLinked) => (),
+      Err(err)                  => return Err(err),
+    }
+    let (parent, side) = self.wind(|elem| self.order(elem, &*node), false).unwrap_or_else(|_| unreachable!());
+    self.link(parent, side, node);
+    Ok(())
+  }
+
+  /// Link `node` into the tree unless an element with an equal key is
+  /// already present, in which case that element is returned instead.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`.
+  #[inline]
+  pub unsafe fn insert_unique(&mut self, node: NonNull<T>) -> Result<(), NonNull<T>> {
+    let node = node.as_ptr();
+    match self.wind(|elem| self.order(elem, &*node), true) {
+      Ok((parent, side)) => {
+        self.link(parent, side, node);
+        Ok(())
+      },
+      Err(equal)         => Err(NonNull::new_unchecked(equal)),
+    }
+  }
+
+  /// Link `node` into the tree. If an element with an equal key is already
+  /// present, `node` takes over its exact position and the evicted element is
+  /// returned, unlinked.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`.
+  #[inline]
+  pub unsafe fn insert_or_replace(&mut self, node: NonNull<T>) -> Option<NonNull<T>> {
+    let node = node.as_ptr();
+    match self.wind(|elem| self.order(elem, &*node), true) {
+      Ok((parent, side)) => {
+        self.link(parent, side, node);
+        None
+      },
+      Err(old)           => {
+        self.replace(old, node);
+        Some(NonNull::new_unchecked(old))
+      },
+    }
+  }
+
+  /// Unlink `node` from the tree by its own links, without searching for it.
+  /// This takes O(log n) time at worst, and amortized O(1) if the nodes keep
+  /// no summary.
+  ///
+  /// # Safety
+  ///
+  /// `node` must be linked into this tree.
+  pub unsafe fn remove(&mut self, node: NonNull<T>) {
+    let node = node.as_ptr();
+    let links = node.field();
+    // The child that takes the place of whichever node is spliced out, and
+    // its new parent
+    let (child, parent, black);
+    if links.left().is_null() || links.right().is_null() {
+      child = if links.left().is_null() { links.right() } else { links.left() };
+      parent = links.parent();
+      black = !links.color();
+      transplant(&mut self.root, node, child);
+    } else {
+      // Swap node with its successor, which has no left child
+      let succ = leftmost(links.right());
+      child = succ.field().right();
+      black = !succ.field().color();
+      if succ.field().parent() == node {
+        parent = succ;
+      } else {
+        parent = succ.field().parent();
+        transplant(&mut self.root, succ, child);
+        succ.field().set_right(links.right());
+        adopt(succ, links.right());
+      }
+      transplant(&mut self.root, node, succ);
+      succ.field().set_left(links.left());
+      adopt(succ, links.left());
+      succ.field().set_color(links.color());
+    }
+    if black {
+      unlink_fixup(&mut self.root, child, parent);
+    }
+    // Rotations on the way keep their own nodes' summaries, and leave the
+    // rest of the changed ones above where the splice was
+    update_up(parent);
+    links.clear();
+    self.len.sub(1);
+  }
+
+  /// Unlink `node` from the tree, or report why it can't be. It is checked
+  /// for being in this tree first, by climbing its parent links in O(log n)
+  /// time.
+  ///
+  /// # Safety
+  ///
+  /// `node` must point to a live element, though it need not be linked into
+  /// this tree.
+  pub unsafe fn try_remove(&mut self, node: NonNull<T>) -> Result<(), TreeError> {
+    self.check_linked(node.as_ptr())?;
+    self.remove(node);
+    Ok(())
+  }
+
+  /// Call `cb` on each element in order, beginning at the first not less than
+  /// `start` if given, until it returns `Some`. Unlike `iter`, `cb` gets the
+  /// tree too, shared so that it can look but not link or unlink.
+  ///
+  /// This steps by parent links, so it takes no more stack than a pointer.
+  pub fn walk<F, R>(&self, start: Option<&A::Key>, cb: &mut F) -> Option<R>
+    where F: FnMut(&Self, NonNull<T>) -> Option<R>
+  {
+    let mut node = match start {
+      Some(start) => nsearch_(self.root, |elem| self.cmp.compare(A::key(elem), start)),
+      None        => leftmost(self.root),
+    };
+    while let Some(elem) = NonNull::new(node) {
+      let ret = cb(self, elem);
+      if ret.is_some() {
+        return ret
+      }
+      node = successor(node);
+    }
+    None
+  }
+
+  /// Call `cb` on each element in reverse order, beginning at the last not
+  /// greater than `start` if given, until it returns `Some`.
+  pub fn reverse_walk<F, R>(&self, start: Option<&A::Key>, cb: &mut F) -> Option<R>
+    where F: FnMut(&Self, NonNull<T>) -> Option<R>
+  {
+    let mut node = match start {
+      Some(start) => psearch_(self.root, |elem| self.cmp.compare(A::key(elem), start)),
+      None        => rightmost(self.root),
+    };
+    while let Some(elem) = NonNull::new(node) {
+      let ret = cb(self, elem);
+      if ret.is_some() {
+        return ret
+      }
+      node = predecessor(node);
+    }
+    None
+  }
+}
+
+impl<T, A, C> ParentTree<T, A, C, Size> where T: Intrusive<ParentNode<T, Size>>
+{
+  /// The number of elements, in O(1) time, from the size of the root's
+  /// subtree.
+  #[inline]
+  pub fn len(&self) -> usize {
+    summary::<T, ParentNode<T, Size>>(self.root).0
+  }
+}
+
+// Order statistics, for trees whose nodes keep the size of their subtrees.
+impl<T, A, C, L> ParentTree<T, A, C, Size, L>
+  where T: Intrusive<ParentNode<T, Size>>, A: KeyAdapter<T>, C: Compare<A::Key>
+{
+
+  /// The element with `k` elements before it, in O(log n) time.
+  #[inline]
+  pub fn select(&self, k: usize) -> Option<NonNull<T>> {
+    NonNull::new(select_(self.root, k))
+  }
+
+  /// The number of elements less than `key`, in O(log n) time.
+  #[inline]
+  pub fn rank<Q>(&self, key: &Q) -> usize
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    rank_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key) == Less)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use alloc::vec::Vec;
+  use core::ptr::NonNull;
+
+  use crate::red_black::test::{check_sizes, check_subtree, counted, key, make, ptr, shuffled, ByKey, Counted,
+                               CountedKey, Elem};
+  use crate::red_black::{is_red, Natural, Size, TreeError};
+  use super::{Entry, ParentNode, ParentTree};
+
+  /// Check the red-black invariants, and that every child links back up to
+  /// its parent.
+  fn check(tree: &ParentTree<Elem, ByKey>) -> Vec<u32> {
+    let mut keys = Vec::new();
+    assert!(!is_red::<_, ParentNode<Elem>>(tree.root), "red root");
+    assert!(tree.root.is_null() || unsafe { &*tree.root }.parent.parent().is_null(), "root has a parent");
+    let parent_links = &mut |node: *mut Elem| {
+      let links = &unsafe { &*node }.parent;
+      for child in [links.left(), links.right()] {
+        assert!(child.is_null() || unsafe { &*child }.parent.parent() == node, "broken parent link");
+      }
+    };
+    check_subtree::<ParentNode<Elem>>(tree.root, &mut keys, parent_links);
+    assert!(keys.windows(2).all(|w| w[0] <= w[1]), "out of order");
+    keys
+  }
+
+  fn build(elems: &[Elem]) -> ParentTree<Elem, ByKey> {
+    let mut tree = ParentTree::new();
+    for elem in elems {
+      unsafe { tree.insert(ptr(elem)) };
+    }
+    tree
+  }
+
+  fn unlinked(elem: &Elem) -> bool {
+    let links = &elem.parent;
+    links.left().is_null() && links.right().is_null() && links.parent().is_null()
+  }
+
+  #[test]
+  fn insert_remove() {
+    let keys: Vec<u32> = shuffled(500).iter().map(|k| k / 2).collect();
+    let elems = make(&keys);
+    let mut tree = build(&elems);
+    assert_eq!(check(&tree), (0..500).map(|k| k / 2).collect::<Vec<_>>());
+
+    for elem in elems.iter().step_by(2) {
+      unsafe { tree.remove(ptr(elem)) };
+      assert!(unlinked(elem));
+      check(&tree);
+    }
+    let mut left: Vec<u32> = keys.iter().skip(1).step_by(2).cloned().collect();
+    left.sort();
+    assert_eq!(check(&tree), left);
+
+    for elem in elems.iter().skip(1).step_by(2) {
+      unsafe { tree.remove(ptr(elem)) };
+    }
+    assert!(tree.is_empty() && tree.first().is_none());
+  }
+
+  #[test]
+  fn navigate() {
+    let keys: Vec<u32> = shuffled(60).iter().map(|k| k / 4).collect();
+    let elems = make(&keys);
+    let tree = build(&elems);
+    let mut node = tree.first();
+    for k in 0..60 {
+      assert_eq!(node.map(key), Some(k / 4));
+      node = unsafe { tree.next(node.unwrap()) };
+    }
+    assert_eq!(node, None);
+    assert_eq!(unsafe { tree.prev(tree.last().unwrap()) }.map(key), Some(14));
+    assert_eq!(tree.lower_bound(&20).map(|e| e.key), None);
+    assert_eq!(tree.upper_bound(&10).map(|e| e.key), Some(11));
+    assert_eq!(tree.psearch(&1000).map(key), Some(14));
+
+    // Equal keys stay in insertion order
+    let threes: Vec<_> = elems.iter().filter(|e| e.key == 3).map(ptr).collect();
+    assert_eq!(tree.count(&3), 4);
+    assert_eq!(tree.equal_range(&3), Some((threes[0], threes[3])));
+    assert_eq!(tree.equal_range(&15), None);
+
+    let mut seen = Vec::new();
+    tree.walk(Some(&13), &mut |_, node| { seen.push(key(node)); None::<()> });
+    assert_eq!(seen, [13, 13, 13, 13, 14, 14, 14, 14]);
+    seen.clear();
+    tree.reverse_walk(Some(&0), &mut |_, node| { seen.push(key(node)); None::<()> });
+    assert_eq!(seen, [0; 4]);
+    assert_eq!(tree.walk(None, &mut |_, node| Some(key(node)).filter(|&k| k > 5)), Some(6));
+  }
+
+  #[test]
+  fn unique() {
+    let elems = make(&shuffled(50));
+    let mut tree = ParentTree::new();
+    for elem in &elems {
+      assert_eq!(unsafe { tree.insert_unique(ptr(elem)) }, Ok(()));
+    }
+    let extra = make(&[20, 20, 50]);
+    assert_eq!(unsafe { tree.insert_unique(ptr(&extra[0])) }.map_err(key), Err(20));
+
+    let old = tree.search(&20).unwrap();
+    assert_eq!(unsafe { tree.insert_or_replace(ptr(&extra[1])) }, Some(old));
+    assert_eq!(tree.search(&20), Some(ptr(&extra[1])));
+    assert!(unlinked(unsafe { old.as_ref() }));
+    assert_eq!(unsafe { tree.insert_or_replace(ptr(&extra[2])) }, None);
+    assert_eq!(check(&tree), (0..51).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn entry() {
+    let elems = make(&(0..40).map(|k| k * 2).collect::<Vec<_>>());
+    let mut tree = build(&elems);
+    let extra = make(&[31, 32]);
+
+    match tree.entry(&31) {
+      Entry::Vacant(entry) => assert_eq!(unsafe { entry.insert(ptr(&extra[0])) }.key, 31),
+      Entry::Occupied(_)   => panic!("31 is not present"),
+    }
+    assert_eq!(unsafe { tree.entry(&31).or_insert(ptr(&extra[1])) }.key, 31);
+    match tree.entry(&32) {
+      Entry::Occupied(entry) => assert_eq!(unsafe { entry.replace(ptr(&extra[1])) }, ptr(&elems[16])),
+      Entry::Vacant(_)       => panic!("32 is present"),
+    }
+    assert_eq!(tree.search(&32), Some(ptr(&extra[1])));
+    for k in [0, 31, 32, 78] {
+      match tree.entry(&k) {
+        Entry::Occupied(entry) => assert_eq!(key(entry.remove()), k),
+        Entry::Vacant(_)       => panic!("{} is present", k),
+      }
+      check(&tree);
+    }
+    assert_eq!(check(&tree).len(), 37);
+  }
+
+  #[test]
+  fn fallible() {
+    let elems = make(&[1, 2, 3, 20]);
+    let mut tree = build(&elems[..3]);
+    let other = build(&elems[3..]);
+    assert_eq!(unsafe { tree.try_insert(ptr(&elems[1])) }, Err(TreeError::DuplicateKey));
+    assert_eq!(unsafe { tree.try_next(ptr(&elems[0])) }.map(|n| n.map(key)), Ok(Some(2)));
+    assert_eq!(unsafe { tree.try_prev(ptr(&elems[3])) }, Err(TreeError::NotLinked));
+    assert_eq!(unsafe { tree.try_remove(ptr(&elems[3])) }, Err(TreeError::NotLinked));
+    assert!(unsafe { tree.cursor_at(ptr(&elems[3])) }.is_err());
+    assert_eq!(other.first().map(key), Some(20));
+
+    // A parent that does not link back down
+    let leaf = tree.first().unwrap();
+    unsafe { leaf.as_ref() }.parent.set_parent(tree.last().unwrap().as_ptr());
+    assert_eq!(unsafe { tree.try_remove(leaf) }, Err(TreeError::CorruptLinks));
+    unsafe { leaf.as_ref() }.parent.set_parent(tree.root);
+    assert_eq!(unsafe { tree.try_remove(leaf) }, Ok(()));
+    assert_eq!(check(&tree), [2, 3]);
+  }
+
+  #[test]
+  fn cursor() {
+    let elems = make(&shuffled(100));
+    let mut tree = build(&elems);
+    let mut cursor = tree.cursor_first();
+    let mut seen = Vec::new();
+    while let Some(elem) = cursor.get() {
+      assert_eq!(cursor.peek_prev().map(|e| e.key), seen.last().cloned());
+      seen.push(elem.key);
+      cursor.move_next();
+    }
+    assert_eq!(seen, (0..100).collect::<Vec<_>>());
+    cursor.move_prev();
+    assert_eq!(cursor.get().map(|e| e.key), Some(99));
+
+    // Unlink the odd elements
+    let mut cursor = tree.cursor_lower_bound_mut(&1);
+    while let Some(node) = cursor.remove_current() {
+      assert_eq!(key(node) % 2, 1);
+      if cursor.get().is_none() {
+        break
+      }
+      cursor.move_next();
+    }
+    assert_eq!(check(&tree), (0..50).map(|k| k * 2).collect::<Vec<_>>());
+
+    // And put some back
+    let odd = make(&[41, 43, 45, 1000]);
+    let at = elems.iter().find(|e| e.key == 42).unwrap();
+    let mut cursor = unsafe { tree.cursor_at_mut(ptr(at)) }.unwrap();
+    assert_eq!(unsafe { cursor.insert_before(ptr(&odd[0])) }, Ok(()));
+    assert_eq!(unsafe { cursor.insert_after(ptr(&odd[1])) }, Ok(()));
+    assert_eq!(unsafe { cursor.insert_after(ptr(&odd[2])) }, Err(TreeError::OutOfOrder));
+    cursor.move_next();
+    cursor.move_next();
+    assert_eq!(unsafe { cursor.replace_current(ptr(&odd[2])) }.map(key), Ok(44));
+    while cursor.get().is_some() {
+      cursor.move_next();
+    }
+    assert_eq!(unsafe { cursor.insert_before(ptr(&odd[3])) }, Ok(()));
+    let keys = check(&tree);
+    assert_eq!(&keys[20..25], &[40, 41, 42, 43, 45]);
+    assert_eq!(keys.last(), Some(&1000));
+  }
+
+  #[test]
+  fn iter() {
+    let keys: Vec<u32> = shuffled(100).iter().map(|k| k / 2).collect();
+    let elems = make(&keys);
+    let mut tree = build(&elems);
+    assert!(tree.iter().rev().map(|e| e.key).eq((0..100).rev().map(|k| k / 2)));
+    for split in [0, 1, 50, 100] {
+      let mut iter = tree.iter();
+      let front = iter.by_ref().take(split).count();
+      assert_eq!(front + iter.rev().count(), 100);
+    }
+    assert_eq!(tree.range(10..13).map(|e| e.key).collect::<Vec<_>>(), [10, 10, 11, 11, 12, 12]);
+    assert_eq!(tree.range(..=1).rev().map(|e| e.key).collect::<Vec<_>>(), [1, 1, 0, 0]);
+    assert_eq!(tree.range(60..).count(), 0);
+    assert_eq!(tree.range(20..20).count(), 0);
+    for elem in tree.range_mut(5..7) {
+      unsafe { elem.get_unchecked_mut() }.rev = 0;
+    }
+    assert_eq!(tree.iter().filter(|e| e.rev == 0).count(), 4);
+    assert_eq!((&mut tree).into_iter().count(), 100);
+
+    let mut seen = Vec::new();
+    tree.clear_with(|node| {
+      assert!(unlinked(unsafe { node.as_ref() }));
+      seen.push(key(node));
+    });
+    seen.sort();
+    assert_eq!(seen, (0..100).map(|k| k / 2).collect::<Vec<_>>());
+    assert!(tree.is_empty());
+
+    // Dropping a drain part way still unlinks the rest, ready for reuse
+    let mut tree = build(&elems);
+    assert_eq!(tree.drain().take(10).count(), 10);
+    assert!(elems.iter().all(unlinked));
+  }
+
+  #[test]
+  fn extract() {
+    let elems = make(&shuffled(200));
+    let mut tree = build(&elems);
+    let taken: Vec<u32> = tree.extract_if(50..150, |e| e.key % 3 == 0).map(key).collect();
+    assert_eq!(taken, (50..150).filter(|k| k % 3 == 0).collect::<Vec<_>>());
+    assert_eq!(check(&tree).len(), 200 - taken.len());
+    tree.retain(|e| e.key % 2 == 1);
+    assert!(check(&tree).iter().all(|k| k % 2 == 1));
+  }
+
+  #[test]
+  fn from_sorted() {
+    for n in [0, 1, 2, 5, 6, 7, 8, 100, 1000] {
+      let elems = make(&(0..n).map(|k| k / 3).collect::<Vec<_>>());
+      let tree = unsafe { ParentTree::<Elem, ByKey>::from_sorted_iter(elems.iter().map(ptr)) };
+      assert_eq!(check(&tree), (0..n).map(|k| k / 3).collect::<Vec<_>>());
+    }
+  }
+
+  #[test]
+  fn split_join() {
+    let keys: Vec<u32> = shuffled(300).iter().map(|k| k / 2).collect();
+    let elems = make(&keys);
+    for at in [0, 1, 37, 75, 149, 150] {
+      let mut left = build(&elems);
+      let mut right = left.split_off(&at);
+      assert_eq!(check(&left), (0..at * 2).map(|k| k / 2).collect::<Vec<_>>());
+      assert_eq!(check(&right), (at * 2..300).map(|k| k / 2).collect::<Vec<_>>());
+      left.append(&mut right);
+      assert!(right.is_empty());
+      assert_eq!(check(&left).len(), 300);
+      left.clear_with(drop);
+    }
+
+    // Appending before, and interleaved
+    let mut low = build(&elems[..100]);
+    let mut high = low.split_off(&25);
+    high.append(&mut low);
+    high.append(&mut build(&elems[100..]));
+    let mut all = (0..300).map(|k| k / 2).collect::<Vec<_>>();
+    assert_eq!(check(&high), all);
+
+    let small = make(&[2000, 2001, 2002]);
+    let pivot = make(&[1000]);
+    let joined = unsafe { ParentTree::join(high, ptr(&pivot[0]), build(&small)) };
+    all.extend([1000, 2000, 2001, 2002]);
+    assert_eq!(check(&joined), all);
+  }
+
+  #[test]
+  fn set_algebra() {
+    use alloc::collections::BTreeSet;
+
+    let ours: Vec<u32> = (0..200).filter(|k| k % 2 == 0).collect();
+    let theirs: Vec<u32> = (100..400).filter(|k| k % 3 == 0).collect();
+    let (ours_set, theirs_set): (BTreeSet<u32>, BTreeSet<u32>) =
+      (ours.iter().cloned().collect(), theirs.iter().cloned().collect());
+    let (a, b) = (make(&ours), make(&theirs));
+
+    type Op = fn(&mut ParentTree<Elem, ByKey>, &mut ParentTree<Elem, ByKey>, &mut dyn FnMut(NonNull<Elem>));
+    let ops: [(Op, Vec<u32>); 4] = [
+      (|x, y, f| x.union(y, f), ours_set.union(&theirs_set).cloned().collect()),
+      (|x, y, f| x.intersection(y, f), ours_set.intersection(&theirs_set).cloned().collect()),
+      (|x, y, f| x.difference(y, f), ours_set.difference(&theirs_set).cloned().collect()),
+      (|x, y, f| x.symmetric_difference(y, f),
+       ours_set.symmetric_difference(&theirs_set).cloned().collect()),
+    ];
+    for (op, expected) in ops {
+      let (mut x, mut y) = (build(&a), build(&b));
+      let mut dropped = Vec::new();
+      op(&mut x, &mut y, &mut |node| dropped.push(node));
+      assert_eq!(check(&x), expected);
+      assert!(y.is_empty());
+      assert_eq!(x.iter().count() + dropped.len(), a.len() + b.len());
+      assert!(dropped.iter().all(|node| unlinked(unsafe { node.as_ref() })));
+      x.clear_with(drop);
+    }
+  }
+
+  #[test]
+  fn length() {
+    let counting = |elems: &[Elem]| {
+      let mut tree = ParentTree::<Elem, ByKey, Natural, (), usize>::new();
+      for elem in elems {
+        unsafe { tree.insert(ptr(elem)) };
+      }
+      tree
+    };
+    let elems = make(&shuffled(300));
+    let mut tree = counting(&elems);
+    assert_eq!((tree.len(), tree.iter().len()), (300, 300));
+    assert_eq!(build(&elems[..0]).iter().size_hint(), (0, None));
+    for elem in &elems[..100] {
+      unsafe { tree.remove(ptr(elem)) };
+    }
+    tree.cursor_first_mut().remove_current();
+    assert_eq!(tree.len(), 199);
+
+    for at in [0, 1, 150, 250, 300] {
+      let mut high = tree.split_off(&at);
+      assert_eq!((tree.len(), high.len()), (tree.iter().count(), high.iter().count()));
+      tree.append(&mut high);
+      assert_eq!((tree.len(), high.len()), (199, 0));
+    }
+    let high = tree.split_off(&150);
+    let pivot = tree.last().unwrap();
+    unsafe { tree.remove(pivot) };
+    let mut tree = unsafe { ParentTree::join(tree, pivot, high) };
+    assert_eq!(tree.len(), 199);
+
+    let mut other = counting(&elems[..100]);
+    tree.difference(&mut other, |_| ());
+    assert_eq!((tree.len(), other.len()), (199, 0));
+    tree.retain(|e| e.key % 3 != 0);
+    let kept = tree.len();
+    assert_eq!(kept, tree.iter().count());
+    assert_eq!(tree.drain().count(), kept);
+    assert_eq!(tree.len(), 0);
+
+    let sorted = make(&(0..50).collect::<Vec<_>>());
+    let tree = unsafe { ParentTree::<Elem, ByKey, Natural, (), usize>::from_sorted_iter(sorted.iter().map(ptr)) };
+    assert_eq!(tree.len(), 50);
+  }
+
+  #[test]
+  fn order_statistics() {
+    let keys: Vec<u32> = shuffled(300).iter().map(|k| k / 2).collect();
+    let elems = counted(&keys);
+    let sizes = check_sizes::<ParentNode<Counted, Size>>;
+    let mut tree: ParentTree<Counted, CountedKey, Natural, Size> = ParentTree::new();
+    for elem in &elems {
+      unsafe { tree.insert(NonNull::from(elem)) };
+    }
+    assert_eq!(sizes(tree.root), 300);
+    assert_eq!(tree.iter().len(), 300);
+    for k in 0..300 {
+      assert_eq!(tree.select(k).map(|node| unsafe { node.as_ref() }.key), Some(k as u32 / 2));
+    }
+    assert_eq!(tree.rank(&70), 140);
+    assert_eq!(tree.fold_range(10..20), Size(20));
+
+    for elem in elems.iter().step_by(3) {
+      unsafe { tree.remove(NonNull::from(elem)) };
+      sizes(tree.root);
+    }
+    assert_eq!(tree.len(), 200);
+
+    let mut right = tree.split_off(&75);
+    assert_eq!(sizes(tree.root) + sizes(right.root), 200);
+    tree.append(&mut right);
+    assert_eq!(sizes(tree.root), 200);
+    assert_eq!(tree.rank(&150), 200);
+  }
+}
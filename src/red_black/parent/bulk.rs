@@ -0,0 +1,92 @@
+use core::cmp::Ordering::Greater;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use crate::red_black::{update, Augment, Compare, Counter, KeyAdapter};
+use super::{adopt, ParentNode, ParentTree};
+
+
+/// Take the head off a list threaded through right links.
+#[inline]
+unsafe fn pop<T, S>(list: &mut *mut T) -> *mut T where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  let node = *list;
+  *list = node.field().right();
+  node
+}
+
+/// Link the first `n` elements of the list threaded through `list`'s right
+/// links into a subtree `depth` levels down a tree `height` levels tall,
+/// returning its root.
+///
+/// Each subtree is split evenly about its middle element, so every level
+/// above `height` is full and those on it are the last. They are made red,
+/// which leaves the black height the same down every path. Recursion only
+/// goes `height` deep, so no more than `usize::BITS`.
+unsafe fn link_sorted<T, S>(list: &mut *mut T, n: usize, depth: u32, height: u32) -> *mut T
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>
+{
+  if n == 0 {
+    return ptr::null_mut()
+  }
+  let half = (n - 1) / 2;
+  let left = link_sorted(list, half, depth + 1, height);
+  let node = pop(list);
+  let right = link_sorted(list, n - 1 - half, depth + 1, height);
+  node.field().reset(ptr::null_mut(), depth == height);
+  node.field().set_left(left);
+  node.field().set_right(right);
+  adopt(node, left);
+  adopt(node, right);
+  update(node);
+  node
+}
+
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// A tree of the elements `iter` yields, which must be in order, linked
+  /// together directly in O(n) time rather than inserted one by one.
+  ///
+  /// The order is only checked in debug builds.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`, for every element.
+  pub unsafe fn from_sorted_iter<I>(iter: I) -> ParentTree<T, A, C, S, L>
+    where C: Default, I: IntoIterator<Item = NonNull<T>>
+  {
+    ParentTree::from_sorted_iter_with(C::default(), iter)
+  }
+
+  /// As `from_sorted_iter`, for a tree ordered by `cmp`.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`, for every element.
+  pub unsafe fn from_sorted_iter_with<I>(cmp: C, iter: I) -> ParentTree<T, A, C, S, L>
+    where I: IntoIterator<Item = NonNull<T>>
+  {
+    let mut tree: ParentTree<T, A, C, S, L> = ParentTree::with_comparator(cmp);
+    // Count the elements, threading them into a list through their right
+    // links on the way.
+    let mut head: *mut T = ptr::null_mut();
+    let mut tail: *mut T = ptr::null_mut();
+    let mut n: usize = 0;
+    for node in iter {
+      let node = node.as_ptr();
+      if tail.is_null() {
+        head = node;
+      } else {
+        debug_assert!(tree.order(&*tail, &*node) != Greater, "from_sorted_iter: input is not sorted");
+        tail.field().set_right(node);
+      }
+      tail = node;
+      n += 1;
+    }
+    // The levels above the last hold 2^height - 1 <= n elements
+    let height = usize::BITS - (n + 1).leading_zeros() - 1;
+    tree.root = link_sorted(&mut head, n, 0, height);
+    tree.len.add(n);
+    tree
+  }
+}
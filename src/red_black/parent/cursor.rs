@@ -0,0 +1,339 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering::{Greater, Less};
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ops::RangeBounds;
+use core::ptr::NonNull;
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use crate::red_black::iter::{above_start, below_end, check_range};
+use crate::red_black::{leftmost, nsearch_, rightmost, Augment, Compare, Counter, KeyAdapter, Natural, TreeError};
+use super::{predecessor, successor, ParentNode, ParentTree};
+
+
+/// A read-only position in a `ParentTree`. It is just the element it is at,
+/// so it can be put at any element without a search, and steps by parent
+/// links in amortized O(1).
+///
+/// Besides the elements, a cursor may be at a "ghost" position between the
+/// last element and the first.
+pub struct Cursor<'a, T, A, C = Natural, S = (), L = ()> {
+  tree: &'a ParentTree<T, A, C, S, L>,
+  node: *mut T,
+}
+
+/// A position in a `ParentTree` at which elements can also be linked and
+/// unlinked.
+pub struct CursorMut<'a, T, A, C = Natural, S = (), L = ()> {
+  tree: &'a mut ParentTree<T, A, C, S, L>,
+  node: *mut T,
+}
+
+/// Unlinks and hands out, in order, the elements within a range that a
+/// predicate picks. Those after the last taken are left alone.
+pub struct ExtractIf<'a, T, A, C, S, L, Q: ?Sized, R, F> {
+  cursor: CursorMut<'a, T, A, C, S, L>,
+  range:  R,
+  pred:   F,
+  _key:   PhantomData<fn(&Q)>,
+}
+
+/// The element after `node`, or the first at the ghost position.
+#[inline]
+fn after<T, S>(root: *mut T, node: *mut T) -> *mut T where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  match node.is_null() {
+    true  => leftmost(root),
+    false => successor(node),
+  }
+}
+
+/// The element before `node`, or the last at the ghost position.
+#[inline]
+fn before<T, S>(root: *mut T, node: *mut T) -> *mut T where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  match node.is_null() {
+    true  => rightmost(root),
+    false => predecessor(node),
+  }
+}
+
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  #[inline]
+  fn lower_bound_node<Q>(&self, key: &Q) -> *mut T
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    nsearch_(self.root, |elem| self.cmp.compare(A::key(elem).borrow(), key))
+  }
+
+  /// A cursor at the first element.
+  #[inline]
+  pub fn cursor_first(&self) -> Cursor<'_, T, A, C, S, L> {
+    Cursor { node: leftmost(self.root), tree: self }
+  }
+
+  /// A cursor at the last element.
+  #[inline]
+  pub fn cursor_last(&self) -> Cursor<'_, T, A, C, S, L> {
+    Cursor { node: rightmost(self.root), tree: self }
+  }
+
+  /// A cursor at the first element not less than `key`.
+  #[inline]
+  pub fn cursor_lower_bound<Q>(&self, key: &Q) -> Cursor<'_, T, A, C, S, L>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    Cursor { node: self.lower_bound_node(key), tree: self }
+  }
+
+  /// A cursor at `node`, which is checked for being in this tree in
+  /// O(log n) time.
+  ///
+  /// # Safety
+  ///
+  /// `node` must point to a live element, though it need not be linked into
+  /// this tree.
+  #[inline]
+  pub unsafe fn cursor_at(&self, node: NonNull<T>) -> Result<Cursor<'_, T, A, C, S, L>, TreeError> {
+    self.check_linked(node.as_ptr())?;
+    Ok(Cursor { node: node.as_ptr(), tree: self })
+  }
+
+  /// A mutable cursor at the first element.
+  #[inline]
+  pub fn cursor_first_mut(&mut self) -> CursorMut<'_, T, A, C, S, L> {
+    CursorMut { node: leftmost(self.root), tree: self }
+  }
+
+  /// A mutable cursor at the last element.
+  #[inline]
+  pub fn cursor_last_mut(&mut self) -> CursorMut<'_, T, A, C, S, L> {
+    CursorMut { node: rightmost(self.root), tree: self }
+  }
+
+  /// A mutable cursor at the first element not less than `key`.
+  #[inline]
+  pub fn cursor_lower_bound_mut<Q>(&mut self, key: &Q) -> CursorMut<'_, T, A, C, S, L>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    CursorMut { node: self.lower_bound_node(key), tree: self }
+  }
+
+  /// A mutable cursor at `node`.
+  ///
+  /// # Safety
+  ///
+  /// As for `cursor_at`.
+  #[inline]
+  pub unsafe fn cursor_at_mut(&mut self, node: NonNull<T>) -> Result<CursorMut<'_, T, A, C, S, L>, TreeError> {
+    self.check_linked(node.as_ptr())?;
+    Ok(CursorMut { node: node.as_ptr(), tree: self })
+  }
+
+  /// Unlink the elements within `range` for which `pred` holds, handing each
+  /// out as it goes.
+  ///
+  /// # Panics
+  ///
+  /// As for `range`.
+  pub fn extract_if<Q, R, F>(&mut self, range: R, pred: F) -> ExtractIf<'_, T, A, C, S, L, Q, R, F>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>, F: FnMut(&T) -> bool
+  {
+    check_range(&self.cmp, &range);
+    let f = |elem: &T| if above_start(&self.cmp, A::key(elem), &range) { Greater } else { Less };
+    let node = nsearch_(self.root, f);
+    ExtractIf {
+      cursor: CursorMut { tree: self, node },
+      range,
+      pred,
+      _key:   PhantomData,
+    }
+  }
+
+  /// Unlink every element for which `f` does not hold.
+  pub fn retain<F>(&mut self, mut f: F) where F: FnMut(&T) -> bool {
+    self.extract_if::<A::Key, _, _>(.., |elem| !f(elem)).for_each(drop)
+  }
+}
+
+impl<'a, T, A, C, S, L> Cursor<'a, T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// The element at the cursor, or `None` at the ghost position.
+  #[inline]
+  pub fn get(&self) -> Option<&'a T> {
+    unsafe { self.node.as_ref() }
+  }
+
+  #[inline]
+  pub fn as_ptr(&self) -> Option<NonNull<T>> {
+    NonNull::new(self.node)
+  }
+
+  #[inline]
+  pub fn move_next(&mut self) {
+    self.node = after(self.tree.root, self.node)
+  }
+
+  #[inline]
+  pub fn move_prev(&mut self) {
+    self.node = before(self.tree.root, self.node)
+  }
+
+  #[inline]
+  pub fn peek_next(&self) -> Option<&'a T> {
+    unsafe { after(self.tree.root, self.node).as_ref() }
+  }
+
+  #[inline]
+  pub fn peek_prev(&self) -> Option<&'a T> {
+    unsafe { before(self.tree.root, self.node).as_ref() }
+  }
+}
+
+impl<'a, T, A, C, S, L> CursorMut<'a, T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// The element at the cursor, or `None` at the ghost position.
+  #[inline]
+  pub fn get(&self) -> Option<&T> {
+    unsafe { self.node.as_ref() }
+  }
+
+  #[inline]
+  pub fn as_ptr(&self) -> Option<NonNull<T>> {
+    NonNull::new(self.node)
+  }
+
+  /// A read-only cursor at the same position.
+  #[inline]
+  pub fn as_cursor(&self) -> Cursor<'_, T, A, C, S, L> {
+    Cursor { tree: self.tree, node: self.node }
+  }
+
+  #[inline]
+  pub fn move_next(&mut self) {
+    self.node = after(self.tree.root, self.node)
+  }
+
+  #[inline]
+  pub fn move_prev(&mut self) {
+    self.node = before(self.tree.root, self.node)
+  }
+
+  #[inline]
+  pub fn peek_next(&self) -> Option<&T> {
+    unsafe { after(self.tree.root, self.node).as_ref() }
+  }
+
+  #[inline]
+  pub fn peek_prev(&self) -> Option<&T> {
+    unsafe { before(self.tree.root, self.node).as_ref() }
+  }
+
+  /// Whether `node` may sit between `prev` and `next`, either of which may be
+  /// null for the ends of the tree.
+  #[inline]
+  unsafe fn fits(&self, prev: *mut T, node: *mut T, next: *mut T) -> bool {
+    (prev.is_null() || self.tree.order(&*prev, &*node) != Greater)
+      && (next.is_null() || self.tree.order(&*node, &*next) != Greater)
+  }
+
+  /// Unlink the element at the cursor, moving to the one after it, in
+  /// amortized O(1) time if the nodes keep no summary. Returns `None` at the
+  /// ghost position.
+  pub fn remove_current(&mut self) -> Option<NonNull<T>> {
+    let node = NonNull::new(self.node)?;
+    self.node = successor(node.as_ptr());
+    unsafe { self.tree.remove(node) };
+    Some(node)
+  }
+
+  /// Link `node` in just before the cursor, or as the last element at the
+  /// ghost position. The cursor stays where it is.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not already be linked into a tree, and must stay put and
+  /// alive until it is removed.
+  pub unsafe fn insert_before(&mut self, node: NonNull<T>) -> Result<(), TreeError> {
+    let node = node.as_ptr();
+    let cur = self.node;
+    if !self.fits(before(self.tree.root, cur), node, cur) {
+      return Err(TreeError::OutOfOrder)
+    }
+    // The leaf just before the cursor: its left link, or the right link of
+    // the last element in its left subtree
+    if cur.is_null() {
+      self.tree.link(rightmost(self.tree.root), Greater, node);
+    } else if cur.field().left().is_null() {
+      self.tree.link(cur, Less, node);
+    } else {
+      self.tree.link(rightmost(cur.field().left()), Greater, node);
+    }
+    Ok(())
+  }
+
+  /// Link `node` in just after the cursor, or as the first element at the
+  /// ghost position. The cursor stays where it is.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert_before`.
+  pub unsafe fn insert_after(&mut self, node: NonNull<T>) -> Result<(), TreeError> {
+    let node = node.as_ptr();
+    let cur = self.node;
+    if !self.fits(cur, node, after(self.tree.root, cur)) {
+      return Err(TreeError::OutOfOrder)
+    }
+    if cur.is_null() {
+      self.tree.link(leftmost(self.tree.root), Less, node);
+    } else if cur.field().right().is_null() {
+      self.tree.link(cur, Greater, node);
+    } else {
+      self.tree.link(leftmost(cur.field().right()), Less, node);
+    }
+    Ok(())
+  }
+
+  /// Put `node` in place of the element at the cursor, returning the evicted
+  /// element. `NotLinked` is reported at the ghost position.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert_before`.
+  pub unsafe fn replace_current(&mut self, node: NonNull<T>) -> Result<NonNull<T>, TreeError> {
+    let node = node.as_ptr();
+    let old = NonNull::new(self.node).ok_or(TreeError::NotLinked)?;
+    if !self.fits(predecessor(old.as_ptr()), node, successor(old.as_ptr())) {
+      return Err(TreeError::OutOfOrder)
+    }
+    self.tree.replace(old.as_ptr(), node);
+    self.node = node;
+    Ok(old)
+  }
+}
+
+impl<'a, T, A, C, S, L, Q, R, F> Iterator for ExtractIf<'a, T, A, C, S, L, Q, R, F>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, A::Key: Borrow<Q>,
+        C: Compare<A::Key> + Compare<Q>, Q: ?Sized, R: RangeBounds<Q>, F: FnMut(&T) -> bool, L: Counter
+{
+  type Item = NonNull<T>;
+
+  fn next(&mut self) -> Option<NonNull<T>> {
+    loop {
+      let elem = self.cursor.get()?;
+      if !below_end(&self.cursor.tree.cmp, A::key(elem), &self.range) {
+        return None
+      }
+      if (self.pred)(elem) {
+        return self.cursor.remove_current()
+      }
+      self.cursor.move_next();
+    }
+  }
+}
+
+impl<'a, T, A, C, S, L, Q, R, F> FusedIterator for ExtractIf<'a, T, A, C, S, L, Q, R, F>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, A::Key: Borrow<Q>,
+        C: Compare<A::Key> + Compare<Q>, Q: ?Sized, R: RangeBounds<Q>, F: FnMut(&T) -> bool, L: Counter { }
@@ -0,0 +1,118 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::ptr::NonNull;
+
+use crate::intrusive::Intrusive;
+use crate::red_black::{Augment, Compare, Counter, KeyAdapter, Natural};
+use super::{ParentNode, ParentTree};
+
+
+/// A position in a `ParentTree` for some key, found by `ParentTree::entry`.
+/// It remembers the element there, or the leaf where one would go, so that
+/// it can be filled or emptied without searching again.
+pub enum Entry<'a, T, A, C = Natural, S = (), L = ()> {
+  Occupied(OccupiedEntry<'a, T, A, C, S, L>),
+  Vacant(VacantEntry<'a, T, A, C, S, L>),
+}
+
+/// A position holding an element.
+pub struct OccupiedEntry<'a, T, A, C = Natural, S = (), L = ()> {
+  tree: &'a mut ParentTree<T, A, C, S, L>,
+  node: *mut T,
+}
+
+/// An empty position, where an element with the key would be linked: the
+/// leaf on `side` of `parent`.
+pub struct VacantEntry<'a, T, A, C = Natural, S = (), L = ()> {
+  tree:   &'a mut ParentTree<T, A, C, S, L>,
+  parent: *mut T,
+  side:   Ordering,
+}
+
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// The position for `key`: an element equal to it, or where one would go.
+  pub fn entry<Q>(&mut self, key: &Q) -> Entry<'_, T, A, C, S, L>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized
+  {
+    match self.wind(|elem| self.cmp.compare(A::key(elem).borrow(), key), true) {
+      Ok((parent, side)) => Entry::Vacant(VacantEntry { tree: self, parent, side }),
+      Err(node)          => Entry::Occupied(OccupiedEntry { tree: self, node }),
+    }
+  }
+}
+
+impl<'a, T, A, C, S, L> Entry<'a, T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// The element present, linking `node` first if there is none.
+  ///
+  /// # Safety
+  ///
+  /// As for `VacantEntry::insert`.
+  #[inline]
+  pub unsafe fn or_insert(self, node: NonNull<T>) -> &'a T {
+    match self {
+      Entry::Occupied(entry) => entry.into_ref(),
+      Entry::Vacant(entry)   => entry.insert(node),
+    }
+  }
+}
+
+impl<'a, T, A, C, S, L> OccupiedEntry<'a, T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  #[inline]
+  pub fn get(&self) -> &T {
+    unsafe { &*self.node }
+  }
+
+  #[inline]
+  pub fn into_ref(self) -> &'a T {
+    unsafe { &*self.node }
+  }
+
+  #[inline]
+  pub fn as_ptr(&self) -> NonNull<T> {
+    unsafe { NonNull::new_unchecked(self.node) }
+  }
+
+  /// Put `node` in the element's exact position, returning the evicted
+  /// element.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not already be linked into a tree, must have a key equal to
+  /// the element's, and must stay put and alive until it is removed.
+  #[inline]
+  pub unsafe fn replace(self, node: NonNull<T>) -> NonNull<T> {
+    let old = self.as_ptr();
+    self.tree.replace(self.node, node.as_ptr());
+    old
+  }
+
+  /// Unlink the element, without comparing any keys.
+  #[inline]
+  pub fn remove(self) -> NonNull<T> {
+    let old = self.as_ptr();
+    unsafe { self.tree.remove(old) };
+    old
+  }
+}
+
+impl<'a, T, A, C, S, L> VacantEntry<'a, T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// Link `node` in at this position, rebalancing up by parent links.
+  ///
+  /// # Safety
+  ///
+  /// `node` must not already be linked into a tree, must have a key equal to
+  /// the one looked up, and must stay put and alive until it is removed.
+  #[inline]
+  pub unsafe fn insert(self, node: NonNull<T>) -> &'a T {
+    self.tree.link(self.parent, self.side, node.as_ptr());
+    &*node.as_ptr()
+  }
+}
@@ -0,0 +1,314 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering::{Greater, Less};
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ops::RangeBounds;
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use crate::red_black::iter::{above_start, below_end, check_range, fold_range};
+use crate::red_black::{leftmost, nsearch_, psearch_, rightmost, summary, Augment, Compare, Counter, KeyAdapter, Size};
+use super::{predecessor, successor, ParentNode, ParentTree};
+
+
+/// Both ends of an in-order walk, which stops once they meet. Each end is
+/// just an element, stepped from by its own links.
+struct Walk<T, S> {
+  front:    *mut T,
+  back:     *mut T,
+  _summary: PhantomData<S>,
+}
+
+impl<T, S> Clone for Walk<T, S> {
+  #[inline]
+  fn clone(&self) -> Walk<T, S> {
+    Walk { front: self.front, back: self.back, _summary: PhantomData }
+  }
+}
+
+impl<T, S> Walk<T, S> where T: Intrusive<ParentNode<T, S>>, S: Augment<T>
+{
+  #[inline]
+  fn new(root: *mut T) -> Walk<T, S> {
+    Walk { front: leftmost(root), back: rightmost(root), _summary: PhantomData }
+  }
+
+  /// A walk over the elements both `above` and `below` hold for, which must
+  /// be a contiguous run.
+  #[inline]
+  fn bounded<F, G>(root: *mut T, mut above: F, mut below: G) -> Walk<T, S>
+    where F: FnMut(&T) -> bool, G: FnMut(&T) -> bool
+  {
+    let front = nsearch_(root, |elem| if above(elem) { Greater } else { Less });
+    let back = psearch_(root, |elem| if below(elem) { Less } else { Greater });
+    let mut walk = Walk { front, back, _summary: PhantomData };
+    // The first element past the lower bound may be past the upper one too
+    if front.is_null() || !below(unsafe { &*front }) {
+      walk.finish();
+    }
+    walk
+  }
+
+  #[inline]
+  fn finish(&mut self) {
+    self.front = ptr::null_mut();
+    self.back = ptr::null_mut();
+  }
+
+  #[inline]
+  fn next(&mut self) -> *mut T {
+    let node = self.front;
+    if node == self.back {
+      self.finish();
+    } else {
+      self.front = successor(node);
+    }
+    node
+  }
+
+  #[inline]
+  fn next_back(&mut self) -> *mut T {
+    let node = self.back;
+    if node == self.front {
+      self.finish();
+    } else {
+      self.back = predecessor(node);
+    }
+    node
+  }
+}
+
+/// An in-order iterator over the elements of a `ParentTree`. It keeps only
+/// its two ends, stepping by the elements' parent links in amortized O(1).
+pub struct Iter<'a, T, S = (), L = ()> {
+  walk:    Walk<T, S>,
+  len:     Option<usize>,
+  _marker: PhantomData<(&'a T, L)>,
+}
+
+/// An in-order iterator over pinned mutable references to the elements of a
+/// `ParentTree`.
+pub struct IterMut<'a, T, S = (), L = ()> {
+  walk:    Walk<T, S>,
+  len:     Option<usize>,
+  _marker: PhantomData<(&'a mut T, L)>,
+}
+
+/// An in-order iterator over the elements of a `ParentTree` within a range of
+/// keys.
+pub struct Range<'a, T, S = ()> {
+  walk:    Walk<T, S>,
+  _marker: PhantomData<&'a T>,
+}
+
+/// An in-order iterator over pinned mutable references to the elements of a
+/// `ParentTree` within a range of keys.
+pub struct RangeMut<'a, T, S = ()> {
+  walk:    Walk<T, S>,
+  _marker: PhantomData<&'a mut T>,
+}
+
+/// Takes the elements out of a `ParentTree` in post-order, climbing back up
+/// by parent links. Those not taken are unlinked when it is dropped.
+pub struct Drain<'a, T, S = ()> where T: Intrusive<ParentNode<T, S>>, S: Augment<T> {
+  next:    *mut T,
+  _marker: PhantomData<(&'a mut T, S)>,
+}
+
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L> where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, L: Counter
+{
+  /// The number of elements, if the tree or its nodes count them.
+  #[inline]
+  fn known_len(&self) -> Option<usize> {
+    self.len.get().or(summary(self.root).count())
+  }
+
+  /// The elements in order.
+  #[inline]
+  pub fn iter(&self) -> Iter<'_, T, S, L> {
+    Iter { walk: Walk::new(self.root), len: self.known_len(), _marker: PhantomData }
+  }
+
+  /// The elements in order, mutably. The keys must not be changed so as to
+  /// reorder them.
+  #[inline]
+  pub fn iter_mut(&mut self) -> IterMut<'_, T, S, L> {
+    IterMut { walk: Walk::new(self.root), len: self.known_len(), _marker: PhantomData }
+  }
+
+  /// Empty the tree, handing out each element once with its links reset, in
+  /// O(n) total.
+  #[inline]
+  pub fn drain(&mut self) -> Drain<'_, T, S> {
+    let root = self.root;
+    self.root = ptr::null_mut();
+    self.len = L::ZERO;
+    Drain::new(root)
+  }
+
+  /// Empty the tree as for `drain`, calling `f` on each element.
+  #[inline]
+  pub fn clear_with<F>(&mut self, f: F) where F: FnMut(NonNull<T>) {
+    self.drain().for_each(f)
+  }
+}
+
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  fn range_walk<Q, R>(&self, range: R) -> Walk<T, S>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    check_range(&self.cmp, &range);
+    let above = |elem: &T| above_start(&self.cmp, A::key(elem), &range);
+    let below = |elem: &T| below_end(&self.cmp, A::key(elem), &range);
+    Walk::bounded(self.root, above, below)
+  }
+
+  /// The elements within `range` in order, as for `BTreeMap::range`.
+  ///
+  /// # Panics
+  ///
+  /// If the range starts after it ends, or starts and ends at the same
+  /// excluded key.
+  #[inline]
+  pub fn range<Q, R>(&self, range: R) -> Range<'_, T, S>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    Range { walk: self.range_walk(range), _marker: PhantomData }
+  }
+
+  /// The elements within `range` in order, mutably. The keys must not be
+  /// changed so as to reorder them.
+  ///
+  /// # Panics
+  ///
+  /// As for `range`.
+  #[inline]
+  pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, T, S>
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    RangeMut { walk: self.range_walk(range), _marker: PhantomData }
+  }
+
+  /// The summary of the elements within `range`, as if they made up a tree
+  /// of their own, in O(log n) time.
+  ///
+  /// # Panics
+  ///
+  /// As for `range`.
+  pub fn fold_range<Q, R>(&self, range: R) -> S
+    where A::Key: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q>
+  {
+    check_range(&self.cmp, &range);
+    let above = |elem: &T| above_start(&self.cmp, A::key(elem), &range);
+    let below = |elem: &T| below_end(&self.cmp, A::key(elem), &range);
+    fold_range(self.root, above, below)
+  }
+}
+
+// Each element is handed out once, and its links are read beforehand, so the
+// mutable references never alias.
+walk_iterator!(ParentNode, Iter<'a>, &'a T, |node: *mut T| unsafe { node.as_ref() }, counted);
+walk_iterator!(ParentNode, IterMut<'a>, Pin<&'a mut T>, |node: *mut T| unsafe {
+  node.as_mut().map(|node| Pin::new_unchecked(node))
+}, counted);
+walk_iterator!(ParentNode, Range<'a>, &'a T, |node: *mut T| unsafe { node.as_ref() });
+walk_iterator!(ParentNode, RangeMut<'a>, Pin<&'a mut T>, |node: *mut T| unsafe {
+  node.as_mut().map(|node| Pin::new_unchecked(node))
+});
+
+impl<'a, T, S, L> Clone for Iter<'a, T, S, L> {
+  #[inline]
+  fn clone(&self) -> Iter<'a, T, S, L> {
+    Iter { walk: self.walk.clone(), len: self.len, _marker: PhantomData }
+  }
+}
+
+impl<'a, T, S> Clone for Range<'a, T, S> {
+  #[inline]
+  fn clone(&self) -> Range<'a, T, S> {
+    Range { walk: self.walk.clone(), _marker: PhantomData }
+  }
+}
+
+impl<'a, T, A, C, S, L> IntoIterator for &'a ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, L: Counter
+{
+  type Item = &'a T;
+  type IntoIter = Iter<'a, T, S, L>;
+
+  #[inline]
+  fn into_iter(self) -> Iter<'a, T, S, L> {
+    self.iter()
+  }
+}
+
+impl<'a, T, A, C, S, L> IntoIterator for &'a mut ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, L: Counter
+{
+  type Item = Pin<&'a mut T>;
+  type IntoIter = IterMut<'a, T, S, L>;
+
+  #[inline]
+  fn into_iter(self) -> IterMut<'a, T, S, L> {
+    self.iter_mut()
+  }
+}
+
+/// The first leaf-most node of the subtree at `node` in post-order.
+#[inline]
+fn deepest_first<T, S>(mut node: *mut T) -> *mut T where T: Intrusive<ParentNode<T, S>> {
+  loop {
+    let links = node.field();
+    node = match (links.left().is_null(), links.right().is_null()) {
+      (false, _)    => links.left(),
+      (true, false) => links.right(),
+      (true, true)  => return node,
+    };
+  }
+}
+
+impl<'a, T, S> Drain<'a, T, S> where T: Intrusive<ParentNode<T, S>>, S: Augment<T>
+{
+  /// Take apart the subtree at `root`, whose parent link must be null.
+  #[inline]
+  pub(super) fn new(root: *mut T) -> Drain<'a, T, S> {
+    let next = match root.is_null() {
+      true  => ptr::null_mut(),
+      false => deepest_first(root),
+    };
+    Drain { next, _marker: PhantomData }
+  }
+}
+
+impl<'a, T, S> Iterator for Drain<'a, T, S> where T: Intrusive<ParentNode<T, S>>, S: Augment<T>
+{
+  type Item = NonNull<T>;
+
+  #[inline]
+  fn next(&mut self) -> Option<NonNull<T>> {
+    let node = NonNull::new(self.next)?.as_ptr();
+    let parent = node.field().parent();
+    // Its parent is next, unless it was the left child and there is a right
+    // subtree to take apart first
+    self.next = if parent.is_null() || parent.field().right().is_null()
+                   || parent.field().right() == node {
+      parent
+    } else {
+      deepest_first(parent.field().right())
+    };
+    node.field().clear();
+    NonNull::new(node)
+  }
+}
+
+impl<'a, T, S> FusedIterator for Drain<'a, T, S> where T: Intrusive<ParentNode<T, S>>, S: Augment<T> { }
+
+impl<'a, T, S> Drop for Drain<'a, T, S> where T: Intrusive<ParentNode<T, S>>, S: Augment<T>
+{
+  fn drop(&mut self) {
+    self.for_each(drop)
+  }
+}
@@ -0,0 +1,202 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering::{Greater, Less};
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use crate::red_black::join::{black_height, split_by, Join, Part};
+use crate::red_black::{is_red, leftmost, update, Augment, Compare, Counter, KeyAdapter, TreeError};
+use super::iter::Drain;
+use super::{adopt, link_fixup, successor, update_up, ParentNode, ParentTree};
+
+
+impl<T, S> Join<T> for ParentNode<T, S> where S: Augment<T>
+{
+  #[inline]
+  fn detach(node: *mut T, height: usize) -> Part<T> where T: Intrusive<ParentNode<T, S>> {
+    if node.is_null() {
+      return (node, height)
+    }
+    node.field().set_parent(ptr::null_mut());
+    if node.field().color() {
+      node.field().set_color(false);
+      (node, height + 1)
+    } else {
+      (node, height)
+    }
+  }
+
+  unsafe fn join_roots(left: *mut T, left_height: usize, pivot: *mut T,
+                       right: *mut T, right_height: usize) -> Part<T>
+    where T: Intrusive<ParentNode<T, S>>
+  {
+    if left_height == right_height {
+      pivot.field().reset(ptr::null_mut(), false);
+      pivot.field().set_left(left);
+      pivot.field().set_right(right);
+      adopt(pivot, left);
+      adopt(pivot, right);
+      update(pivot);
+      return (pivot, left_height + 1)
+    }
+    // Down the taller tree's near spine to a black node of the shorter one's
+    // height, to be the pivot's other child
+    let taller_left = left_height > right_height;
+    let (mut root, height) = match taller_left {
+      true  => (left, left_height),
+      false => (right, right_height),
+    };
+    let mut h = height;
+    let mut parent = ptr::null_mut();
+    let mut node = root;
+    while h > left_height.min(right_height) || is_red(node) {
+      h -= !is_red(node) as usize;
+      parent = node;
+      node = match taller_left {
+        true  => node.field().right(),
+        false => node.field().left(),
+      };
+    }
+    pivot.field().reset(parent, true);
+    if taller_left {
+      pivot.field().set_left(node);
+      pivot.field().set_right(right);
+      parent.field().set_right(pivot);
+    } else {
+      pivot.field().set_left(left);
+      pivot.field().set_right(node);
+      parent.field().set_left(pivot);
+    }
+    adopt(pivot, pivot.field().left());
+    adopt(pivot, pivot.field().right());
+    update(pivot);
+    let grew = link_fixup(&mut root, pivot);
+    update_up(pivot);
+    (root, height + grew as usize)
+  }
+
+  #[inline]
+  fn unlink(node: *mut T) where T: Intrusive<ParentNode<T, S>> {
+    node.field().clear();
+  }
+
+  #[inline]
+  fn drain<F>(root: *mut T, f: F) where T: Intrusive<ParentNode<T, S>>, F: FnMut(NonNull<T>) {
+    Drain::new(root).for_each(f)
+  }
+}
+
+/// The number of elements in the smaller of the trees rooted at `a` and `b`,
+/// and whether that is `a`'s, in time proportional to it.
+fn count_smaller<T, S>(a: *mut T, b: *mut T) -> (usize, bool)
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>
+{
+  let (mut a, mut b) = (leftmost(a), leftmost(b));
+  let mut n = 0;
+  loop {
+    if a.is_null() {
+      return (n, true)
+    }
+    if b.is_null() {
+      return (n, false)
+    }
+    a = successor(a);
+    b = successor(b);
+    n += 1;
+  }
+}
+
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// A tree of the elements of `left`, then `pivot`, then those of `right`,
+  /// in O(log n) time. It keeps `left`'s comparator.
+  ///
+  /// No element of `left` may be greater than `pivot`, nor `pivot` greater
+  /// than any of `right`. This is only checked in debug builds.
+  ///
+  /// # Safety
+  ///
+  /// As for `insert`, for `pivot`.
+  pub unsafe fn join(left: ParentTree<T, A, C, S, L>, pivot: NonNull<T>, right: ParentTree<T, A, C, S, L>)
+                     -> ParentTree<T, A, C, S, L>
+  {
+    let pivot = pivot.as_ptr();
+    debug_assert!(left.last().is_none_or(|last| left.order(last.as_ref(), &*pivot) != Greater),
+                  "join: left tree is not before pivot");
+    debug_assert!(right.first().is_none_or(|first| left.order(&*pivot, first.as_ref()) != Greater),
+                  "join: right tree is not after pivot");
+    let (root, _) = ParentNode::join_roots(left.root, black_height(left.root), pivot,
+                                           right.root, black_height(right.root));
+    let mut len = left.len;
+    len.add(1 + right.len.get().unwrap_or(0));
+    ParentTree { root, len, cmp: left.cmp, _adapter: PhantomData }
+  }
+
+  /// Move every element of `other` into this tree, leaving `other` empty.
+  ///
+  /// If all of `other` goes after all of this tree or before it, this is
+  /// O(log n); otherwise the elements are inserted one by one.
+  pub fn append(&mut self, other: &mut ParentTree<T, A, C, S, L>) {
+    let Some(first) = other.first() else {
+      return
+    };
+    let Some(last) = self.last() else {
+      mem::swap(&mut self.root, &mut other.root);
+      mem::swap(&mut self.len, &mut other.len);
+      return
+    };
+    unsafe {
+      if self.order(last.as_ref(), first.as_ref()) != Greater {
+        other.remove(first);
+        self.root = ParentNode::join_roots(self.root, black_height(self.root), first.as_ptr(),
+                                           other.root, black_height(other.root)).0;
+        self.len.add(1 + mem::replace(&mut other.len, L::ZERO).get().unwrap_or(0));
+        other.root = ptr::null_mut();
+        return
+      }
+      let (first, last) = (self.first().unwrap(), other.last().unwrap());
+      if self.order(last.as_ref(), first.as_ref()) == Less {
+        other.remove(last);
+        self.root = ParentNode::join_roots(other.root, black_height(other.root), last.as_ptr(),
+                                           self.root, black_height(self.root)).0;
+        self.len.add(1 + mem::replace(&mut other.len, L::ZERO).get().unwrap_or(0));
+        other.root = ptr::null_mut();
+        return
+      }
+      // Interleaved, so in order to keep equal keys in insertion order
+      while let Some(node) = other.first() {
+        other.remove(node);
+        self.insert(node);
+      }
+    }
+  }
+
+  /// Move every element not less than `key` into a new tree, in O(log n)
+  /// time, or O(log n + min(k, n - k)) for k elements moved if the tree
+  /// counts its elements, as for `Tree::split_off`.
+  #[inline]
+  pub fn split_off<Q>(&mut self, key: &Q) -> ParentTree<T, A, C, S, L>
+    where A::Key: Borrow<Q>, C: Compare<Q> + Clone, Q: ?Sized
+  {
+    self.try_split_off(key).unwrap_or_else(|err| panic!("{}", err))
+  }
+
+  /// As `split_off`, or report corrupt links, leaving the tree as it was.
+  pub fn try_split_off<Q>(&mut self, key: &Q) -> Result<ParentTree<T, A, C, S, L>, TreeError>
+    where A::Key: Borrow<Q>, C: Compare<Q> + Clone, Q: ?Sized
+  {
+    let f = |elem: &T| self.cmp.compare(A::key(elem).borrow(), key) == Less;
+    let (left, right) = unsafe { split_by::<T, ParentNode<T, S>, _>(self.root, f)? };
+    self.root = left.0;
+    let mut len = L::ZERO;
+    if let Some(n) = self.len.get() {
+      let (smaller, left_is) = count_smaller(left.0, right.0);
+      let moved = if left_is { n - smaller } else { smaller };
+      self.len.sub(moved);
+      len.add(moved);
+    }
+    Ok(ParentTree { root: right.0, len, cmp: self.cmp.clone(), _adapter: PhantomData })
+  }
+}
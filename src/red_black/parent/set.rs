@@ -0,0 +1,78 @@
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::Intrusive;
+use crate::red_black::join::{black_height, Part};
+use crate::red_black::set::{difference, intersection, symmetric_difference, union};
+use crate::red_black::{Augment, Compare, Counter, KeyAdapter};
+use super::{ParentNode, ParentTree};
+
+
+// The set operations are shared with `Tree`; see there for their cost and how
+// they treat equal keys.
+impl<T, A, C, S, L> ParentTree<T, A, C, S, L>
+  where T: Intrusive<ParentNode<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// Take both trees' elements as parts, counting them all as this tree's
+  /// and leaving `other` empty.
+  #[inline]
+  fn take_parts(&mut self, other: &mut ParentTree<T, A, C, S, L>) -> (Part<T>, Part<T>) {
+    let ours = (self.root, black_height(self.root));
+    let theirs = (other.root, black_height(other.root));
+    self.root = ptr::null_mut();
+    other.root = ptr::null_mut();
+    self.len.add(mem::replace(&mut other.len, L::ZERO).get().unwrap_or(0));
+    (ours, theirs)
+  }
+
+  /// Move the elements of `other` in, leaving it empty. Where both trees have
+  /// a key, this tree's element is kept and `other`'s is handed to `dropped`.
+  pub fn union<F>(&mut self, other: &mut ParentTree<T, A, C, S, L>, mut dropped: F) where F: FnMut(NonNull<T>) {
+    let (ours, theirs) = self.take_parts(other);
+    let order = |a: &T, b: &T| self.order(a, b);
+    let mut n = 0;
+    let mut counted = |node| { n += 1; dropped(node) };
+    self.root = unsafe { union(&order, ours, theirs, &mut counted) }.0;
+    self.len.sub(n);
+  }
+
+  /// Keep only the elements whose keys are also in `other`, leaving it empty.
+  /// Every other element of either tree is handed to `dropped`.
+  pub fn intersection<F>(&mut self, other: &mut ParentTree<T, A, C, S, L>, mut dropped: F)
+    where F: FnMut(NonNull<T>)
+  {
+    let (ours, theirs) = self.take_parts(other);
+    let order = |a: &T, b: &T| self.order(a, b);
+    let mut n = 0;
+    let mut counted = |node| { n += 1; dropped(node) };
+    self.root = unsafe { intersection(&order, ours, theirs, &mut counted) }.0;
+    self.len.sub(n);
+  }
+
+  /// Keep only the elements whose keys are not in `other`, leaving it empty.
+  /// Every other element of either tree is handed to `dropped`.
+  pub fn difference<F>(&mut self, other: &mut ParentTree<T, A, C, S, L>, mut dropped: F)
+    where F: FnMut(NonNull<T>)
+  {
+    let (ours, theirs) = self.take_parts(other);
+    let order = |a: &T, b: &T| self.order(a, b);
+    let mut n = 0;
+    let mut counted = |node| { n += 1; dropped(node) };
+    self.root = unsafe { difference(&order, ours, theirs, &mut counted) }.0;
+    self.len.sub(n);
+  }
+
+  /// Keep the elements whose keys are in only one of the trees, moving in
+  /// those from `other` and leaving it empty. The elements of both trees with
+  /// keys in both are handed to `dropped`.
+  pub fn symmetric_difference<F>(&mut self, other: &mut ParentTree<T, A, C, S, L>, mut dropped: F)
+    where F: FnMut(NonNull<T>)
+  {
+    let (ours, theirs) = self.take_parts(other);
+    let order = |a: &T, b: &T| self.order(a, b);
+    let mut n = 0;
+    let mut counted = |node| { n += 1; dropped(node) };
+    self.root = unsafe { symmetric_difference(&order, ours, theirs, &mut counted) }.0;
+    self.len.sub(n);
+  }
+}
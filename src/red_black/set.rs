@@ -0,0 +1,216 @@
+use core::cmp::Ordering::{self, Greater, Less};
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use crate::intrusive::{Intrusive, IntrusiveExt};
+use super::join::{black_height, split_by, Join, Part};
+use super::{intact, rightmost, Augment, Compare, Counter, KeyAdapter, Node, Tree};
+
+
+/// Unlink `node` and hand it to `dropped`.
+#[inline]
+fn drop_one<T, L, F>(node: *mut T, dropped: &mut F) where T: Intrusive<L>, L: Join<T>, F: FnMut(NonNull<T>) {
+  L::unlink(node);
+  dropped(unsafe { NonNull::new_unchecked(node) })
+}
+
+/// Unlink every element of `part` and hand each to `dropped`.
+#[inline]
+fn drop_all<T, L, F>(part: Part<T>, dropped: &mut F) where T: Intrusive<L>, L: Join<T>, F: FnMut(NonNull<T>) {
+  L::drain(part.0, dropped)
+}
+
+/// The root of `part` and its two subtrees, cut loose.
+#[inline]
+fn expose<T, L>(part: Part<T>) -> (Part<T>, *mut T, Part<T>) where T: Intrusive<L>, L: Join<T> {
+  let (node, height) = part;
+  (L::detach(node.field().left(), height - 1), node, L::detach(node.field().right(), height - 1))
+}
+
+/// `left` followed by `right`.
+pub(super) unsafe fn join2<T, L>(left: Part<T>, right: Part<T>) -> Part<T> where T: Intrusive<L>, L: Join<T> {
+  if left.0.is_null() {
+    return right
+  }
+  let last = rightmost(left.0);
+  let (left, _) = intact(split_by(left.0, |elem| !ptr::eq(elem, last)));
+  L::join_roots(left.0, left.1, last, right.0, right.1)
+}
+
+/// `left`, then the elements of `mid`, then `right`.
+unsafe fn join3<T, L>(left: Part<T>, mid: Part<T>, right: Part<T>) -> Part<T> where T: Intrusive<L>, L: Join<T> {
+  let node = mid.0;
+  if node.is_null() {
+    join2(left, right)
+  } else if node.field().left().is_null() && node.field().right().is_null() {
+    L::join_roots(left.0, left.1, node, right.0, right.1)
+  } else {
+    join2(join2(left, mid), right)
+  }
+}
+
+// The set operations split one tree around each element of the other, then
+// join the results back up. For trees of m and n elements, m <= n, that is
+// O(m log(n / m + 1)). They are shared by `Tree` and `ParentTree`, ordering
+// elements by `order`.
+//
+// They recurse, but only as deep as the other tree is tall, and so at most
+// `2 * usize::BITS` levels, each with a small frame.
+//
+// They are meant for trees whose keys are unique within each. Otherwise the
+// result is still in order, but which of the equal elements are kept is up to
+// the shapes of the trees.
+
+/// Split `part` into the elements before `node`'s key, equal to it, and after
+/// it.
+#[inline]
+unsafe fn split3<T, L, O>(order: &O, part: Part<T>, node: *mut T) -> (Part<T>, Part<T>, Part<T>)
+  where T: Intrusive<L>, L: Join<T>, O: Fn(&T, &T) -> Ordering
+{
+  let (less, rest) = intact(split_by(part.0, |elem| order(elem, &*node) == Less));
+  let (equal, greater) = intact(split_by(rest.0, |elem| order(elem, &*node) != Greater));
+  (less, equal, greater)
+}
+
+pub(super) unsafe fn union<T, L, O, F>(order: &O, ours: Part<T>, theirs: Part<T>, dropped: &mut F) -> Part<T>
+  where T: Intrusive<L>, L: Join<T>, O: Fn(&T, &T) -> Ordering, F: FnMut(NonNull<T>)
+{
+  if ours.0.is_null() {
+    return theirs
+  }
+  if theirs.0.is_null() {
+    return ours
+  }
+  let (theirs_left, node, theirs_right) = expose(theirs);
+  let (less, equal, greater) = split3(order, ours, node);
+  let left = union(order, less, theirs_left, dropped);
+  let right = union(order, greater, theirs_right, dropped);
+  if equal.0.is_null() {
+    L::join_roots(left.0, left.1, node, right.0, right.1)
+  } else {
+    drop_one(node, dropped);
+    join3(left, equal, right)
+  }
+}
+
+pub(super) unsafe fn intersection<T, L, O, F>(order: &O, ours: Part<T>, theirs: Part<T>, dropped: &mut F) -> Part<T>
+  where T: Intrusive<L>, L: Join<T>, O: Fn(&T, &T) -> Ordering, F: FnMut(NonNull<T>)
+{
+  if ours.0.is_null() || theirs.0.is_null() {
+    drop_all(ours, dropped);
+    drop_all(theirs, dropped);
+    return (ptr::null_mut(), 0)
+  }
+  let (theirs_left, node, theirs_right) = expose(theirs);
+  let (less, equal, greater) = split3(order, ours, node);
+  let left = intersection(order, less, theirs_left, dropped);
+  let right = intersection(order, greater, theirs_right, dropped);
+  drop_one(node, dropped);
+  join3(left, equal, right)
+}
+
+pub(super) unsafe fn difference<T, L, O, F>(order: &O, ours: Part<T>, theirs: Part<T>, dropped: &mut F) -> Part<T>
+  where T: Intrusive<L>, L: Join<T>, O: Fn(&T, &T) -> Ordering, F: FnMut(NonNull<T>)
+{
+  if ours.0.is_null() || theirs.0.is_null() {
+    drop_all(theirs, dropped);
+    return ours
+  }
+  let (theirs_left, node, theirs_right) = expose(theirs);
+  let (less, equal, greater) = split3(order, ours, node);
+  let left = difference(order, less, theirs_left, dropped);
+  let right = difference(order, greater, theirs_right, dropped);
+  drop_one(node, dropped);
+  drop_all(equal, dropped);
+  join2(left, right)
+}
+
+pub(super) unsafe fn symmetric_difference<T, L, O, F>(order: &O, ours: Part<T>, theirs: Part<T>, dropped: &mut F)
+                                                     -> Part<T>
+  where T: Intrusive<L>, L: Join<T>, O: Fn(&T, &T) -> Ordering, F: FnMut(NonNull<T>)
+{
+  if ours.0.is_null() {
+    return theirs
+  }
+  if theirs.0.is_null() {
+    return ours
+  }
+  let (theirs_left, node, theirs_right) = expose(theirs);
+  let (less, equal, greater) = split3(order, ours, node);
+  let left = symmetric_difference(order, less, theirs_left, dropped);
+  let right = symmetric_difference(order, greater, theirs_right, dropped);
+  if equal.0.is_null() {
+    L::join_roots(left.0, left.1, node, right.0, right.1)
+  } else {
+    drop_one(node, dropped);
+    drop_all(equal, dropped);
+    join2(left, right)
+  }
+}
+
+impl<T, A, C, S, L> Tree<T, A, C, S, L>
+  where T: Intrusive<Node<T, S>>, S: Augment<T>, A: KeyAdapter<T>, C: Compare<A::Key>, L: Counter
+{
+  /// Take both trees' elements as parts, counting them all as this tree's
+  /// and leaving `other` empty.
+  #[inline]
+  fn take_parts(&mut self, other: &mut Tree<T, A, C, S, L>) -> (Part<T>, Part<T>) {
+    let ours = (self.root, black_height(self.root));
+    let theirs = (other.root, black_height(other.root));
+    self.root = ptr::null_mut();
+    other.root = ptr::null_mut();
+    self.len.add(mem::replace(&mut other.len, L::ZERO).get().unwrap_or(0));
+    (ours, theirs)
+  }
+
+  /// Move the elements of `other` in, leaving it empty. Where both trees have
+  /// a key, this tree's element is kept and `other`'s is handed to `dropped`.
+  pub fn union<F>(&mut self, other: &mut Tree<T, A, C, S, L>, mut dropped: F) where F: FnMut(NonNull<T>) {
+    let (ours, theirs) = self.take_parts(other);
+    let order = |a: &T, b: &T| self.order(a, b);
+    let mut n = 0;
+    let mut counted = |node| { n += 1; dropped(node) };
+    self.root = unsafe { union(&order, ours, theirs, &mut counted) }.0;
+    self.len.sub(n);
+  }
+
+  /// Keep only the elements whose keys are also in `other`, leaving it empty.
+  /// Every other element of either tree is handed to `dropped`.
+  pub fn intersection<F>(&mut self, other: &mut Tree<T, A, C, S, L>, mut dropped: F)
+    where F: FnMut(NonNull<T>)
+  {
+    let (ours, theirs) = self.take_parts(other);
+    let order = |a: &T, b: &T| self.order(a, b);
+    let mut n = 0;
+    let mut counted = |node| { n += 1; dropped(node) };
+    self.root = unsafe { intersection(&order, ours, theirs, &mut counted) }.0;
+    self.len.sub(n);
+  }
+
+  /// Keep only the elements whose keys are not in `other`, leaving it empty.
+  /// Every other element of either tree is handed to `dropped`.
+  pub fn difference<F>(&mut self, other: &mut Tree<T, A, C, S, L>, mut dropped: F)
+    where F: FnMut(NonNull<T>)
+  {
+    let (ours, theirs) = self.take_parts(other);
+    let order = |a: &T, b: &T| self.order(a, b);
+    let mut n = 0;
+    let mut counted = |node| { n += 1; dropped(node) };
+    self.root = unsafe { difference(&order, ours, theirs, &mut counted) }.0;
+    self.len.sub(n);
+  }
+
+  /// Keep the elements whose keys are in only one of the trees, moving in
+  /// those from `other` and leaving it empty. The elements of both trees with
+  /// keys in both are handed to `dropped`.
+  pub fn symmetric_difference<F>(&mut self, other: &mut Tree<T, A, C, S, L>, mut dropped: F)
+    where F: FnMut(NonNull<T>)
+  {
+    let (ours, theirs) = self.take_parts(other);
+    let order = |a: &T, b: &T| self.order(a, b);
+    let mut n = 0;
+    let mut counted = |node| { n += 1; dropped(node) };
+    self.root = unsafe { symmetric_difference(&order, ours, theirs, &mut counted) }.0;
+    self.len.sub(n);
+  }
+}